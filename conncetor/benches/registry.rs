@@ -0,0 +1,142 @@
+use std::{
+    hint::black_box,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use connector::{
+    consist_hash::HashAlgorithm,
+    registry::{
+        ServiceRegistry,
+        discovery::Discovery,
+        model::{Registry, ServiceEntry, ServiceInfo},
+        store::{BalanceStrategy, BalancedStore, Store},
+    },
+};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+const INSTANCES: usize = 32;
+
+/// Reports a different instance missing on every call, so that every refresh
+/// removes one instance and adds another one back.
+#[derive(Debug, Default)]
+struct FlappingDiscovery {
+    round: AtomicUsize,
+}
+
+#[tonic::async_trait]
+impl Discovery for FlappingDiscovery {
+    async fn discover(&self, service: &str) -> anyhow::Result<Vec<ServiceEntry>> {
+        let missing = self.round.fetch_add(1, Ordering::Relaxed) % INSTANCES;
+
+        let entries = (0..INSTANCES)
+            .filter(|instance| *instance != missing)
+            .map(|instance| {
+                ServiceEntry::new(ServiceInfo::new(
+                    format!("{}-{}", service, instance),
+                    service.to_string(),
+                    "127.0.0.1".to_string(),
+                    9000 + instance as u16,
+                ))
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    async fn register(&self, _service: Registry, _ttl: Duration) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn reregister(&self, _service: Registry) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+fn registry(strategy: BalanceStrategy) -> Arc<ServiceRegistry<()>> {
+    let store = BalancedStore::new(strategy, 100, HashAlgorithm::XxHash64.hasher(), None);
+
+    Arc::new(ServiceRegistry::new(
+        Arc::new(FlappingDiscovery::default()),
+        "upstream",
+        store,
+    ))
+}
+
+/// Picks while a task refreshes the registry back to back, which is far more
+/// often than the periodic refresh ever does.
+///
+/// Like the periodic refresh, a single task writes the store: concurrent
+/// writers are not synchronized and could drop each other's updates.
+fn pick_under_refresh(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let keys: Vec<String> = (0..1024).map(|key| format!("user-{}", key)).collect();
+
+    let mut group = c.benchmark_group("pick_under_refresh");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, strategy) in [
+        ("ring", BalanceStrategy::ConsistHash),
+        ("rendezvous", BalanceStrategy::Rendezvous),
+        ("round_robin", BalanceStrategy::RoundRobin),
+    ] {
+        for refreshing in [false, true] {
+            let registry = registry(strategy);
+
+            runtime
+                .block_on(registry.update_store(|_| async { Ok(()) }))
+                .unwrap();
+
+            let stop = Arc::new(AtomicBool::new(false));
+            let refreshes = Arc::new(AtomicUsize::new(0));
+
+            let refresher = refreshing.then(|| {
+                let registry = registry.clone();
+                let stop = stop.clone();
+                let refreshes = refreshes.clone();
+
+                runtime.spawn(async move {
+                    while !stop.load(Ordering::Relaxed) {
+                        registry.update_store(|_| async { Ok(()) }).await.unwrap();
+
+                        refreshes.fetch_add(1, Ordering::Relaxed);
+
+                        tokio::task::yield_now().await;
+                    }
+                })
+            });
+
+            // Only start picking once the refresher is running.
+            while refresher.is_some() && refreshes.load(Ordering::Relaxed) == 0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            let label = if refreshing { "refreshing" } else { "idle" };
+
+            group.bench_function(format!("{}/{}", name, label), |b| {
+                let mut keys = keys.iter().cycle();
+
+                b.iter(|| registry.store().pick(black_box(keys.next().unwrap())))
+            });
+
+            stop.store(true, Ordering::Relaxed);
+
+            if let Some(refresher) = refresher {
+                runtime.block_on(refresher).unwrap();
+            }
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, pick_under_refresh);
+criterion_main!(benches);
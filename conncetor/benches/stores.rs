@@ -0,0 +1,88 @@
+use std::{collections::HashMap, hint::black_box};
+
+use connector::{
+    consist_hash::HashAlgorithm,
+    registry::{
+        model::{ServiceEntry, ServiceInfo},
+        store::{BalanceStrategy, BalancedStore, ServiceData, Store},
+    },
+};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+
+const STRATEGIES: [(&str, BalanceStrategy); 3] = [
+    ("ring", BalanceStrategy::ConsistHash),
+    ("rendezvous", BalanceStrategy::Rendezvous),
+    ("jump", BalanceStrategy::JumpHash),
+];
+
+fn instances(count: usize) -> Vec<ServiceData<()>> {
+    (0..count)
+        .map(|ordinal| {
+            let info = ServiceInfo::new(
+                format!("instance-{}", ordinal),
+                "upstream".to_string(),
+                "127.0.0.1".to_string(),
+                9000,
+            )
+            .with_meta(HashMap::from([(
+                "ordinal".to_string(),
+                ordinal.to_string(),
+            )]));
+
+            ServiceData::new(ServiceEntry::new(info), ())
+        })
+        .collect()
+}
+
+fn store(strategy: BalanceStrategy, count: usize) -> BalancedStore<()> {
+    let mut store = BalancedStore::new(strategy, 100, HashAlgorithm::XxHash64.hasher(), None);
+
+    store.update(instances(count));
+
+    store
+}
+
+fn pick(c: &mut Criterion) {
+    let keys: Vec<String> = (0..1024).map(|key| format!("user-{}", key)).collect();
+
+    let mut group = c.benchmark_group("pick");
+
+    for count in [8, 64] {
+        for (name, strategy) in STRATEGIES {
+            let store = store(strategy, count);
+
+            group.bench_with_input(BenchmarkId::new(name, count), &keys, |b, keys| {
+                let mut keys = keys.iter().cycle();
+
+                b.iter(|| store.pick(black_box(keys.next().unwrap())))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+/// Cost of taking a membership change, i.e. a discovery refresh adding one
+/// instance.
+fn update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update");
+
+    for count in [8, 64] {
+        for (name, strategy) in STRATEGIES {
+            let datas = instances(count + 1);
+
+            group.bench_with_input(BenchmarkId::new(name, count), &datas, |b, datas| {
+                b.iter_batched(
+                    || store(strategy, count),
+                    |mut store| store.update(datas.clone()),
+                    criterion::BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, pick, update);
+criterion_main!(benches);
@@ -1,3 +1,5 @@
+use std::{env, path::PathBuf};
+
 fn main() {
     const USER_PROTO_FILE: &str = "../proto/user_service.proto";
     const MESSAGE_PROTO_FILE: &str = "../proto/message_service.proto";
@@ -10,20 +12,17 @@ fn main() {
         .unwrap_or_else(|err| panic!("Failed to compile protos: {}", err));
     tonic_prost_build::compile_protos(CHANNEL_PROTO_FILE)
         .unwrap_or_else(|err| panic!("Failed to compile protos: {}", err));
-    tonic_prost_build::compile_protos(DISPATCH_PROTO_FILE)
+
+    // The descriptor set of the served protos backs gRPC server reflection.
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("Cargo sets OUT_DIR"));
+
+    tonic_prost_build::configure()
+        .file_descriptor_set_path(out_dir.join("dispatch_service_descriptor.bin"))
+        .compile_protos(&[DISPATCH_PROTO_FILE], &["../proto"])
         .unwrap_or_else(|err| panic!("Failed to compile protos: {}", err));
 
-    println!("{}", format!("cargo:rerun-if-changed={}", USER_PROTO_FILE));
-    println!(
-        "{}",
-        format!("cargo:rerun-if-changed={}", MESSAGE_PROTO_FILE)
-    );
-    println!(
-        "{}",
-        format!("cargo:rerun-if-changed={}", CHANNEL_PROTO_FILE)
-    );
-    println!(
-        "{}",
-        format!("cargo:rerun-if-changed={}", DISPATCH_PROTO_FILE)
-    );
+    println!("cargo:rerun-if-changed={}", USER_PROTO_FILE);
+    println!("cargo:rerun-if-changed={}", MESSAGE_PROTO_FILE);
+    println!("cargo:rerun-if-changed={}", CHANNEL_PROTO_FILE);
+    println!("cargo:rerun-if-changed={}", DISPATCH_PROTO_FILE);
 }
@@ -1,6 +1,14 @@
 use anyhow::anyhow;
 use redis::{AsyncTypedCommands, Client, RedisResult};
 
+/// Hash of the connector every online user is connected to.
+pub const USER_CONNECTOR_KEY: &str = "user:connector";
+
+/// How a connector is referred to in `USER_CONNECTOR_KEY`.
+pub fn connector_token(service_name: &str, service_id: &str) -> String {
+    format!("{}:{}", service_name, service_id)
+}
+
 #[derive(Debug)]
 pub struct CacheClient {
     remote: Client,
@@ -30,31 +38,6 @@ impl CacheClient {
         Ok(())
     }
 
-    pub async fn set_ex_nx(
-        &self,
-        key: &str,
-        value: &str,
-        ttl_sec: Option<i64>,
-    ) -> RedisResult<bool> {
-        let conn = &mut self.remote.get_multiplexed_async_connection().await?;
-
-        // If ttl is Some, pass it as ARGV[2], otherwise pass an empty string
-        let result = match ttl_sec {
-            Some(ttl) => {
-                let mut pipeline = redis::pipe();
-
-                pipeline
-                    .set_nx(key, value)
-                    .expire(key, ttl)
-                    .query_async(conn)
-                    .await?
-            }
-            None => conn.set_nx(key, value).await?,
-        };
-
-        Ok(result)
-    }
-
     pub async fn hash_set(&self, hash_key: &str, field: &str, value: &str) -> RedisResult<bool> {
         let conn = &mut self.remote.get_multiplexed_async_connection().await?;
 
@@ -70,6 +53,71 @@ impl CacheClient {
         }
     }
 
+    /// Returns the values of the fields, in the same order.
+    pub async fn hash_get_many(
+        &self,
+        hash_key: &str,
+        fields: &[String],
+    ) -> RedisResult<Vec<Option<String>>> {
+        if fields.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let conn = &mut self.remote.get_multiplexed_async_connection().await?;
+
+        redis::cmd("HMGET")
+            .arg(hash_key)
+            .arg(fields)
+            .query_async(conn)
+            .await
+    }
+
+    /// Appends the value to every list in one round trip, keeping the last
+    /// `max_len` values of each list and expiring it `ttl_sec` after the push.
+    /// Nothing is appended when `max_len` is 0.
+    pub async fn list_push_capped(
+        &self,
+        keys: &[String],
+        value: &str,
+        max_len: usize,
+        ttl_sec: i64,
+    ) -> RedisResult<()> {
+        // `LTRIM key 0 -1` would keep the whole list rather than nothing.
+        if keys.is_empty() || max_len == 0 {
+            return Ok(());
+        }
+
+        let conn = &mut self.remote.get_multiplexed_async_connection().await?;
+
+        let mut pipeline = redis::pipe();
+
+        for key in keys {
+            pipeline
+                .rpush(key, value)
+                .ignore()
+                .ltrim(key, -(max_len as isize), -1)
+                .ignore()
+                .expire(key, ttl_sec)
+                .ignore();
+        }
+
+        pipeline.query_async(conn).await
+    }
+
+    /// Removes a list and returns its values.
+    pub async fn list_take(&self, key: &str) -> RedisResult<Vec<String>> {
+        let conn = &mut self.remote.get_multiplexed_async_connection().await?;
+
+        let (values, _): (Vec<String>, i64) = redis::pipe()
+            .atomic()
+            .lrange(key, 0, -1)
+            .del(key)
+            .query_async(conn)
+            .await?;
+
+        Ok(values)
+    }
+
     pub async fn hash_delete(&self, hash_key: &str, field: &str) -> RedisResult<bool> {
         let conn = &mut self.remote.get_multiplexed_async_connection().await?;
 
@@ -85,3 +133,47 @@ impl CacheClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A client of a server that is not there, so every command fails.
+    fn unreachable() -> CacheClient {
+        CacheClient {
+            remote: Client::open("redis://127.0.0.1:1").unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn pushes_nothing_when_the_lists_hold_nothing() {
+        let cache = unreachable();
+        let keys = ["offline:alice".to_string()];
+
+        assert!(
+            cache
+                .list_push_capped(&keys, "message", 0, 60)
+                .await
+                .is_ok()
+        );
+        assert!(
+            cache
+                .list_push_capped(&keys, "message", 1, 60)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn pushes_nothing_without_keys() {
+        let cache = unreachable();
+
+        assert!(
+            cache
+                .list_push_capped(&[], "message", 100, 60)
+                .await
+                .is_ok()
+        );
+        assert!(cache.hash_get_many("hash", &[]).await.unwrap().is_empty());
+    }
+}
@@ -1,4 +1,15 @@
-use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Deserializer, de::Error as _};
+
+use crate::{
+    consist_hash::HashAlgorithm,
+    registry::{
+        model::InstanceFilter,
+        store::{BalanceStrategy, BreakerConfig},
+    },
+    service::{RetryBudgetConfig, RetryPolicy, RoutingKey, UpstreamRpc},
+};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct AppConfig {
@@ -7,16 +18,152 @@ pub struct AppConfig {
 
     http_host: String,
     http_port: u16,
+    /// Listener of the operator endpoints, `/admin/upstreams` and `/metrics`,
+    /// kept apart from the public one. They are not served when unset.
+    #[serde(default = "AppConfig::default_admin_host")]
+    admin_host: String,
+    #[serde(default)]
+    admin_port: Option<u16>,
+    /// Whether clients reach the WebSocket endpoint over TLS, e.g. terminated
+    /// by a proxy in front of the connector, so that `GET /connect` hands out
+    /// `wss://` URLs.
+    #[serde(default)]
+    websock_tls: bool,
+    /// WebSocket connections a connector is sized for, advertised to the front
+    /// door along with its current connection count.
+    #[serde(default = "AppConfig::default_max_connections")]
+    max_connections: usize,
+    /// Messages waiting to be sent on a WebSocket connection before dispatching
+    /// to the user is pushed back.
+    #[serde(default = "AppConfig::default_user_queue_capacity")]
+    user_queue_capacity: usize,
+    /// Dispatched messages kept per user while the user is not connected to
+    /// any connector, to be sent once it connects again. The offline queue is
+    /// off while 0, the default.
+    #[serde(default)]
+    offline_queue_len: usize,
+    #[serde(default = "AppConfig::default_offline_queue_ttl_secs")]
+    offline_queue_ttl_secs: u64,
+    /// Extra tags registered with both the gRPC and the WebSocket endpoint.
+    #[serde(default)]
+    service_tags: Vec<String>,
+    /// How `GET /connect` picks the connector a client should use.
+    #[serde(default)]
+    assign_policy: AssignPolicy,
+    /// Key signing the connection tickets. When set, WebSocket upgrades are
+    /// refused without a valid ticket.
+    #[serde(default)]
+    ticket_secret: Option<String>,
+    #[serde(default = "AppConfig::default_ticket_ttl_secs")]
+    ticket_ttl_secs: u64,
+    /// Bearer token of the backends allowed to ask `GET /connect` for tickets,
+    /// after authenticating the user themselves. While tickets are signed and
+    /// this is unset, `/connect` refuses every caller.
+    #[serde(default)]
+    assign_token: Option<String>,
 
     grpc_host: String,
     grpc_port: u16,
     refresh_ttl_secs: u64,
+    /// Let Consul probe the gRPC health service instead of waiting for the
+    /// connector to refresh a TTL check.
+    #[serde(default)]
+    grpc_health_check: bool,
+    #[serde(default = "AppConfig::default_grpc_check_interval_secs")]
+    grpc_check_interval_secs: u64,
+    /// How long the connector reports itself as not ready before shutting
+    /// down, giving load balancers time to steer traffic away.
+    #[serde(default)]
+    drain_grace_secs: u64,
+    /// TLS of the dispatch server and the upstream channels, plaintext when
+    /// unset.
+    #[serde(default)]
+    grpc_tls: GrpcTlsConfig,
+    /// How dispatchers authenticate to the `DispatchService`, which accepts
+    /// any caller when unset. Set it only once every dispatcher sends the
+    /// matching credentials, see their `dispatch_auth_kind`.
+    #[serde(default)]
+    dispatch_auth: Option<DispatchAuthConfig>,
 
     consul_host: String,
     consul_port: u16,
+    /// Talk to Consul over HTTPS instead of plain HTTP.
+    #[serde(default)]
+    consul_https: bool,
+    /// ACL token sent with every Consul request.
+    #[serde(default)]
+    consul_token: Option<String>,
+    /// PEM file of the CA used to verify the Consul server.
+    #[serde(default)]
+    consul_ca_cert: Option<String>,
+    /// PEM files of the client certificate and its private key.
+    #[serde(default)]
+    consul_client_cert: Option<String>,
+    #[serde(default)]
+    consul_client_key: Option<String>,
+    #[serde(default)]
+    consul_datacenter: Option<String>,
+    #[serde(default)]
+    consul_namespace: Option<String>,
+
+    #[serde(default)]
+    discovery: DiscoveryConfig,
+
+    /// Connect to upstream instances on first use instead of during refresh.
+    #[serde(default)]
+    lazy_connect: bool,
+    #[serde(default = "AppConfig::default_connect_timeout_ms")]
+    connect_timeout_ms: u64,
+    /// Deadline of upstream calls, unless the upstream sets its own.
+    #[serde(default = "AppConfig::default_rpc_timeout_ms")]
+    rpc_timeout_ms: u64,
+    /// Limits the retries of all upstream calls together.
+    #[serde(default)]
+    retry_budget: RetryBudgetConfig,
+
+    /// Directory where the last discovered instances of every upstream are
+    /// persisted, to start from when discovery is unreachable.
+    #[serde(default)]
+    registry_snapshot_dir: Option<String>,
+
+    /// Per upstream service settings, keyed by service name.
+    #[serde(default)]
+    upstreams: HashMap<String, UpstreamConfig>,
 }
 
 impl AppConfig {
+    fn default_admin_host() -> String {
+        "127.0.0.1".to_string()
+    }
+
+    fn default_max_connections() -> usize {
+        10_000
+    }
+
+    fn default_user_queue_capacity() -> usize {
+        64
+    }
+
+    fn default_offline_queue_ttl_secs() -> u64 {
+        86_400
+    }
+
+    fn default_ticket_ttl_secs() -> u64 {
+        30
+    }
+
+    fn default_grpc_check_interval_secs() -> u64 {
+        10
+    }
+
+    fn default_connect_timeout_ms() -> u64 {
+        3_000
+    }
+
+    fn default_rpc_timeout_ms() -> u64 {
+        5_000
+    }
+
     pub fn try_from_file(path: Option<&str>) -> anyhow::Result<Self> {
         let path = path.unwrap_or("app_config.json");
 
@@ -43,6 +190,63 @@ impl AppConfig {
         self.http_port
     }
 
+    pub fn admin_host(&self) -> &str {
+        &self.admin_host
+    }
+
+    pub fn admin_port(&self) -> Option<u16> {
+        self.admin_port
+    }
+
+    pub fn websock_tls(&self) -> bool {
+        self.websock_tls
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    pub fn user_queue_capacity(&self) -> usize {
+        self.user_queue_capacity
+    }
+
+    pub fn offline_queue_len(&self) -> usize {
+        self.offline_queue_len
+    }
+
+    pub fn offline_queue_ttl_secs(&self) -> u64 {
+        self.offline_queue_ttl_secs
+    }
+
+    pub fn service_tags(&self) -> &[String] {
+        &self.service_tags
+    }
+
+    /// Name the WebSocket endpoint is registered under.
+    pub fn websock_service_name(&self) -> String {
+        format!("{}-ws", self.service_name)
+    }
+
+    pub fn websock_service_id(&self) -> String {
+        format!("{}-ws", self.service_id)
+    }
+
+    pub fn assign_policy(&self) -> AssignPolicy {
+        self.assign_policy
+    }
+
+    pub fn ticket_secret(&self) -> Option<&str> {
+        self.ticket_secret.as_deref()
+    }
+
+    pub fn ticket_ttl(&self) -> Duration {
+        Duration::from_secs(self.ticket_ttl_secs)
+    }
+
+    pub fn assign_token(&self) -> Option<&str> {
+        self.assign_token.as_deref()
+    }
+
     pub fn grpc_host(&self) -> &str {
         &self.grpc_host
     }
@@ -55,6 +259,26 @@ impl AppConfig {
         self.refresh_ttl_secs
     }
 
+    pub fn grpc_health_check(&self) -> bool {
+        self.grpc_health_check
+    }
+
+    pub fn grpc_check_interval(&self) -> Duration {
+        Duration::from_secs(self.grpc_check_interval_secs)
+    }
+
+    pub fn drain_grace(&self) -> Duration {
+        Duration::from_secs(self.drain_grace_secs)
+    }
+
+    pub fn grpc_tls(&self) -> &GrpcTlsConfig {
+        &self.grpc_tls
+    }
+
+    pub fn dispatch_auth(&self) -> Option<&DispatchAuthConfig> {
+        self.dispatch_auth.as_ref()
+    }
+
     pub fn consul_host(&self) -> &str {
         &self.consul_host
     }
@@ -62,4 +286,349 @@ impl AppConfig {
     pub fn consul_port(&self) -> u16 {
         self.consul_port
     }
+
+    pub fn consul_https(&self) -> bool {
+        self.consul_https
+    }
+
+    pub fn consul_token(&self) -> Option<&str> {
+        self.consul_token.as_deref()
+    }
+
+    pub fn consul_ca_cert(&self) -> Option<&str> {
+        self.consul_ca_cert.as_deref()
+    }
+
+    pub fn consul_client_cert(&self) -> Option<&str> {
+        self.consul_client_cert.as_deref()
+    }
+
+    pub fn consul_client_key(&self) -> Option<&str> {
+        self.consul_client_key.as_deref()
+    }
+
+    pub fn consul_datacenter(&self) -> Option<&str> {
+        self.consul_datacenter.as_deref()
+    }
+
+    pub fn consul_namespace(&self) -> Option<&str> {
+        self.consul_namespace.as_deref()
+    }
+
+    pub fn discovery(&self) -> &DiscoveryConfig {
+        &self.discovery
+    }
+
+    pub fn lazy_connect(&self) -> bool {
+        self.lazy_connect
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms)
+    }
+
+    pub fn retry_budget(&self) -> &RetryBudgetConfig {
+        &self.retry_budget
+    }
+
+    pub fn registry_snapshot_dir(&self) -> Option<&str> {
+        self.registry_snapshot_dir.as_deref()
+    }
+
+    pub fn upstream(&self, service_name: &str) -> UpstreamConfig {
+        let mut upstream = self
+            .upstreams
+            .get(service_name)
+            .cloned()
+            .unwrap_or_default();
+
+        upstream.timeout_ms.get_or_insert(self.rpc_timeout_ms);
+
+        upstream
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GrpcTlsConfig {
+    #[serde(default)]
+    pub server: Option<ServerTlsConfig>,
+    #[serde(default)]
+    pub client: Option<ClientTlsConfig>,
+    /// How often the certificate files are checked for changes.
+    #[serde(default = "GrpcTlsConfig::default_reload_interval_secs")]
+    pub reload_interval_secs: u64,
+}
+
+impl Default for GrpcTlsConfig {
+    fn default() -> Self {
+        Self {
+            server: None,
+            client: None,
+            reload_interval_secs: Self::default_reload_interval_secs(),
+        }
+    }
+}
+
+impl GrpcTlsConfig {
+    fn default_reload_interval_secs() -> u64 {
+        30
+    }
+
+    pub fn reload_interval(&self) -> Duration {
+        Duration::from_secs(self.reload_interval_secs)
+    }
+}
+
+/// PEM files the dispatch server presents and verifies clients against.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ServerTlsConfig {
+    pub cert: String,
+    pub key: String,
+    /// CA the certificates of the dispatchers are verified against.
+    #[serde(default)]
+    pub client_ca: Option<String>,
+    /// Refuse clients without a certificate signed by `client_ca`.
+    #[serde(default)]
+    pub require_client_cert: bool,
+}
+
+/// PEM files the upstream channels verify instances against and present.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ClientTlsConfig {
+    pub ca_cert: String,
+    #[serde(default)]
+    pub cert: Option<String>,
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Name the certificates of the instances are issued for, defaulting to
+    /// the address they registered with.
+    #[serde(default)]
+    pub server_name: Option<String>,
+}
+
+/// Credentials the dispatchers send in the metadata of their calls.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DispatchAuthConfig {
+    /// `authorization: Bearer <secret>`.
+    SharedSecret { secret: String },
+    /// `x-dispatch-signature` is the base64url HMAC-SHA256 of
+    /// `"{x-dispatcher-id}\n{x-dispatch-timestamp}\n{x-dispatch-nonce}"`, the
+    /// timestamp being Unix seconds no further than `max_skew_secs` from the
+    /// connector clock. A nonce is accepted once per dispatcher, so a captured
+    /// signature cannot be replayed.
+    ///
+    /// The messages themselves are not signed, so the dispatch server must run
+    /// with TLS for them not to be tampered with.
+    Hmac {
+        secret: String,
+        #[serde(default = "DispatchAuthConfig::default_max_skew_secs")]
+        max_skew_secs: u64,
+    },
+}
+
+impl DispatchAuthConfig {
+    fn default_max_skew_secs() -> u64 {
+        60
+    }
+}
+
+/// Strategy of the connector assignment endpoint.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignPolicy {
+    /// The connector with the lowest share of its capacity in use.
+    #[default]
+    LeastLoaded,
+    /// The connector owning the user on the connectors ring, so that
+    /// reconnects land on the same node.
+    Sticky,
+}
+
+/// Backend used to discover upstream services and register the connector.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DiscoveryConfig {
+    /// Query the Consul agent at `consul_host:consul_port`.
+    #[default]
+    Consul,
+    /// Serve a fixed list of instances per service name.
+    Static {
+        services: HashMap<String, Vec<StaticInstance>>,
+    },
+    /// Resolve `_<service>._tcp.<domain>` SRV records.
+    DnsSrv { domain: String },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StaticInstance {
+    pub id: String,
+    pub address: String,
+    pub port: u16,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub meta: HashMap<String, String>,
+    #[serde(default)]
+    pub weight: Option<u32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UpstreamConfig {
+    #[serde(default)]
+    filter: InstanceFilter,
+    #[serde(default)]
+    strategy: BalanceStrategy,
+    /// Virtual nodes per instance on the consistent hash ring.
+    #[serde(default = "UpstreamConfig::default_replicas")]
+    replicas: usize,
+    #[serde(default)]
+    hasher: HashAlgorithm,
+    /// Enables bounded-load consistent hashing, letting an instance take up to
+    /// `1 + load_factor` times the average load before keys spill over.
+    #[serde(default, deserialize_with = "deserialize_load_factor")]
+    load_factor: Option<f64>,
+    /// How many other instances an idempotent call may fail over to when an
+    /// instance is unreachable.
+    #[serde(default = "UpstreamConfig::default_max_failovers")]
+    max_failovers: usize,
+    /// Overrides the routing key of individual RPCs.
+    #[serde(default)]
+    routing: HashMap<UpstreamRpc, RoutingKey>,
+    /// How long the instances may go without a successful refresh before the
+    /// upstream is reported stale and the connector not ready.
+    #[serde(default = "UpstreamConfig::default_stale_after_secs")]
+    stale_after_secs: u64,
+    #[serde(default)]
+    stale_policy: StalePolicy,
+    /// Deadline of every call to the upstream, defaulting to `rpc_timeout_ms`.
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    /// Overrides the deadline of individual RPCs.
+    #[serde(default)]
+    rpc_timeouts_ms: HashMap<UpstreamRpc, u64>,
+    #[serde(default)]
+    retry: RetryPolicy,
+    #[serde(default)]
+    breaker: BreakerConfig,
+}
+
+/// What to do with the instances of an upstream once they are stale.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StalePolicy {
+    /// Keep routing to the last known instances.
+    #[default]
+    KeepLast,
+    /// Drop all instances, failing calls fast until discovery recovers.
+    Empty,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            filter: InstanceFilter::default(),
+            strategy: BalanceStrategy::default(),
+            replicas: Self::default_replicas(),
+            hasher: HashAlgorithm::default(),
+            load_factor: None,
+            max_failovers: Self::default_max_failovers(),
+            routing: HashMap::new(),
+            stale_after_secs: Self::default_stale_after_secs(),
+            stale_policy: StalePolicy::default(),
+            timeout_ms: None,
+            rpc_timeouts_ms: HashMap::new(),
+            retry: RetryPolicy::default(),
+            breaker: BreakerConfig::default(),
+        }
+    }
+}
+
+impl UpstreamConfig {
+    fn default_replicas() -> usize {
+        5
+    }
+
+    fn default_max_failovers() -> usize {
+        1
+    }
+
+    fn default_stale_after_secs() -> u64 {
+        60
+    }
+
+    pub fn filter(&self) -> &InstanceFilter {
+        &self.filter
+    }
+
+    pub fn strategy(&self) -> BalanceStrategy {
+        self.strategy
+    }
+
+    pub fn replicas(&self) -> usize {
+        self.replicas
+    }
+
+    pub fn hasher(&self) -> HashAlgorithm {
+        self.hasher
+    }
+
+    pub fn load_factor(&self) -> Option<f64> {
+        self.load_factor
+    }
+
+    pub fn max_failovers(&self) -> usize {
+        self.max_failovers
+    }
+
+    pub fn routing_key(&self, rpc: UpstreamRpc) -> RoutingKey {
+        self.routing
+            .get(&rpc)
+            .copied()
+            .unwrap_or_else(|| rpc.default_routing_key())
+    }
+
+    pub fn stale_after(&self) -> Duration {
+        Duration::from_secs(self.stale_after_secs)
+    }
+
+    pub fn stale_policy(&self) -> StalePolicy {
+        self.stale_policy
+    }
+
+    pub fn timeout(&self, rpc: UpstreamRpc) -> Duration {
+        let timeout_ms = self
+            .rpc_timeouts_ms
+            .get(&rpc)
+            .copied()
+            .or(self.timeout_ms)
+            .unwrap_or_else(AppConfig::default_rpc_timeout_ms);
+
+        Duration::from_millis(timeout_ms)
+    }
+
+    pub fn retry(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    pub fn breaker(&self) -> &BreakerConfig {
+        &self.breaker
+    }
+}
+
+/// A load factor of zero or less would leave no room above the average load,
+/// so only positive finite values are accepted.
+fn deserialize_load_factor<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let load_factor = Option::<f64>::deserialize(deserializer)?;
+
+    match load_factor {
+        Some(epsilon) if !(epsilon.is_finite() && epsilon > 0.0) => Err(D::Error::custom(format!(
+            "load_factor must be a positive number, got {}",
+            epsilon
+        ))),
+        _ => Ok(load_factor),
+    }
 }
@@ -1,9 +1,71 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use serde::Deserialize;
 
 pub type Hasher = fn(&str) -> u64;
 
-/// Consistent Hash Ring implementation with virtual nodes (replicas).
+/// Hash functions a ring can be configured with.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    #[default]
+    XxHash64,
+    XxHash3,
+    Fnv1a,
+}
+
+impl HashAlgorithm {
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::XxHash64 => xx_hash64,
+            Self::XxHash3 => xx_hash3,
+            Self::Fnv1a => fnv1a,
+        }
+    }
+}
+
+fn xx_hash64(key: &str) -> u64 {
+    use std::hash::Hasher as StdHasher;
+
+    let mut hasher = twox_hash::XxHash64::with_seed(0);
+
+    hasher.write(key.as_bytes());
+
+    hasher.finish()
+}
+
+fn xx_hash3(key: &str) -> u64 {
+    use std::hash::Hasher as StdHasher;
+
+    let mut hasher = twox_hash::XxHash3_64::with_seed(0);
+
+    hasher.write(key.as_bytes());
+
+    hasher.finish()
+}
+
+fn fnv1a(key: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    key.bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+/// How evenly the hash space is split between the real nodes of a ring.
 #[derive(Debug)]
+pub struct RingDistribution {
+    /// Fraction of the hash space owned by each real node.
+    pub shares: HashMap<String, f64>,
+    pub min_share: f64,
+    pub max_share: f64,
+    /// Ratio of the largest share to the ideal `1 / nodes` share.
+    pub max_over_ideal: f64,
+}
+
+/// Consistent Hash Ring implementation with virtual nodes (replicas).
+#[derive(Clone, Debug)]
 pub struct ConsistHashRing {
     hasher: Hasher,
 
@@ -13,30 +75,75 @@ pub struct ConsistHashRing {
     virt_map: HashMap<u64, String>,
     /// Number of virtual nodes per real node.
     replicas: usize,
+    /// Real node identifiers, ordered so placement does not depend on the
+    /// order nodes joined in.
+    nodes: BTreeSet<String>,
 }
 
 impl ConsistHashRing {
+    /// Upper bound of rehashes when a virtual node lands on a taken position.
+    const MAX_PROBES: usize = 16;
+
     pub fn new(replicas: usize, hasher: Hasher) -> Self {
         Self {
             hasher,
             ring: Vec::new(),
             virt_map: HashMap::new(),
-            replicas,
+            replicas: replicas.max(1),
+            nodes: BTreeSet::new(),
         }
     }
 
+    /// Adds `real_node` to the ring, doing nothing if it is already there.
     pub fn add_node(&mut self, real_node: &str) {
-        // Calculate hashes for every virtual nodes and add them to the ring.
-        for i in 0..self.replicas {
-            let virtual_node_id = format!("{}-{}", real_node, i);
-            let hash = (self.hasher)(&virtual_node_id);
+        self.add_nodes([real_node]);
+    }
 
-            self.ring.push(hash);
+    /// Adds every node not yet on the ring, placing virtual nodes only once.
+    pub fn add_nodes<'a>(&mut self, real_nodes: impl IntoIterator<Item = &'a str>) {
+        let mut changed = false;
+
+        for real_node in real_nodes {
+            changed |= self.nodes.insert(real_node.to_string());
+        }
 
-            self.virt_map.insert(hash, real_node.to_string());
+        if changed {
+            self.place();
         }
+    }
+
+    /// Lays out the virtual nodes of every real node from scratch.
+    ///
+    /// Nodes are placed in ID order, so when two virtual nodes collide the
+    /// smaller ID keeps the position and the other one probes. The layout thus
+    /// only depends on the set of nodes, not on the order they were added in.
+    fn place(&mut self) {
+        self.ring.clear();
+        self.virt_map.clear();
+
+        for real_node in &self.nodes {
+            // Calculate hashes for every virtual nodes and add them to the ring.
+            for i in 0..self.replicas {
+                // A virtual node colliding with an existing one is rehashed
+                // with a probe suffix rather than taking over its position.
+                let hash = (0..Self::MAX_PROBES)
+                    .map(|probe| match probe {
+                        0 => (self.hasher)(&format!("{}-{}", real_node, i)),
+                        _ => (self.hasher)(&format!("{}-{}#{}", real_node, i, probe)),
+                    })
+                    .find(|hash| !self.virt_map.contains_key(hash));
 
-        // Keep the ring sorted after adding new nodes.
+                let Some(hash) = hash else {
+                    continue;
+                };
+
+                self.ring.push(hash);
+
+                self.virt_map.insert(hash, real_node.clone());
+            }
+        }
+
+        // Keep the ring sorted for the binary searches.
         self.ring.sort_unstable();
     }
 
@@ -64,34 +171,342 @@ impl ConsistHashRing {
         }
     }
 
-    pub fn remove_node(&mut self, real_node: &str) {
-        // Remove all virtual nodes associated with the real node.
-        for i in 0..self.replicas {
-            let virtual_node_id = format!("{}-{}", real_node, i);
-            let hash = (self.hasher)(&virtual_node_id);
-
-            // Remove from ring
-            if let Ok(pos) = self.ring.binary_search(&hash) {
-                self.ring.remove(pos);
-            }
+    /// Consistent hashing with bounded loads.
+    ///
+    /// Every node may hold at most `ceil((1 + epsilon) * (total + 1) / nodes)`
+    /// in-flight requests, as reported by `load_of`. Starting from the node
+    /// owning `key`, the ring is walked clockwise past nodes already at that
+    /// capacity, so a hot key spills over to the next nodes instead of
+    /// overloading its owner. Keys keep their usual node whenever it has room.
+    ///
+    /// `epsilon` must be positive, otherwise no node may be below capacity.
+    pub fn get_node_bounded<F>(&self, key: &str, epsilon: f64, load_of: F) -> Option<&str>
+    where
+        F: Fn(&str) -> usize,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let total: usize = self.nodes.iter().map(|node| load_of(node.as_str())).sum();
+
+        let capacity =
+            ((1.0 + epsilon) * (total + 1) as f64 / self.nodes.len() as f64).ceil() as usize;
+
+        // The capacity is above the average load, so some node always fits;
+        // falling back to the owner only guards against racing load updates.
+        self.walk(key)
+            .find(|node| load_of(node) < capacity)
+            .or_else(|| self.get_node(key))
+    }
 
-            // Remove from virt_map
-            self.virt_map.remove(&hash);
+    /// Returns up to `count` distinct real nodes in ring order starting from
+    /// the owner of `key`, i.e. the owner followed by its fallbacks.
+    pub fn get_nodes(&self, key: &str, count: usize) -> Vec<&str> {
+        self.walk(key).take(count).collect()
+    }
+
+    /// Iterates over the distinct real nodes met when walking the ring
+    /// clockwise from the position of `key`.
+    fn walk(&self, key: &str) -> impl Iterator<Item = &str> {
+        let hash = (self.hasher)(key);
+
+        let start = self.ring.partition_point(|&virt_hash| virt_hash < hash);
+
+        let mut seen = HashSet::new();
+
+        (0..self.ring.len())
+            .map(move |offset| self.ring[(start + offset) % self.ring.len()])
+            .filter_map(move |virt_hash| self.virt_map.get(&virt_hash).map(|node| node.as_str()))
+            .filter(move |node| seen.insert(*node))
+    }
+
+    /// Removes `real_node` from the ring, doing nothing if it is not there.
+    pub fn remove_node(&mut self, real_node: &str) {
+        // Placing again lets nodes that probed around `real_node` take back
+        // their unprobed positions.
+        if self.nodes.remove(real_node) {
+            self.place();
         }
     }
 
     pub fn clear(&mut self) {
         self.ring.clear();
         self.virt_map.clear();
+        self.nodes.clear();
     }
 
     pub fn len(&self) -> usize {
         self.virt_map.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.virt_map.is_empty()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Computes the share of the hash space owned by every real node, where a
+    /// virtual node owns the arc between its predecessor and itself.
+    pub fn distribution(&self) -> RingDistribution {
+        const SPACE: f64 = u64::MAX as f64 + 1.0;
+
+        let mut shares: HashMap<String, f64> =
+            self.nodes.iter().map(|node| (node.clone(), 0.0)).collect();
+
+        for (index, hash) in self.ring.iter().enumerate() {
+            let prev = self.ring[(index + self.ring.len() - 1) % self.ring.len()];
+
+            let arc = match self.ring.len() {
+                1 => SPACE,
+                _ => hash.wrapping_sub(prev) as f64,
+            };
+
+            if let Some(share) = self
+                .virt_map
+                .get(hash)
+                .and_then(|node| shares.get_mut(node))
+            {
+                *share += arc / SPACE;
+            }
+        }
+
+        let min_share = shares.values().copied().fold(f64::INFINITY, f64::min);
+        let max_share = shares.values().copied().fold(0.0, f64::max);
+
+        RingDistribution {
+            max_over_ideal: max_share * shares.len() as f64,
+            min_share: if shares.is_empty() { 0.0 } else { min_share },
+            max_share,
+            shares,
+        }
+    }
 }
 
 // compile-time check: will fail with helpful compiler message showing which field/type is !Sync
-fn assert_sync<T: Sync>() {}
-fn assert_consist_hash_sync() {
+const _: fn() = || {
+    fn assert_sync<T: Sync>() {}
+
     assert_sync::<crate::consist_hash::ConsistHashRing>();
+};
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const KEYS: usize = 10_000;
+
+    fn ring_with(nodes: usize, replicas: usize) -> ConsistHashRing {
+        let mut ring = ConsistHashRing::new(replicas, HashAlgorithm::XxHash64.hasher());
+
+        for node in 0..nodes {
+            ring.add_node(&format!("node-{}", node));
+        }
+
+        ring
+    }
+
+    fn owners(ring: &ConsistHashRing) -> Vec<String> {
+        (0..KEYS)
+            .map(|key| ring.get_node(&format!("key-{}", key)).unwrap().to_string())
+            .collect()
+    }
+
+    /// Places one request per key in turn, checking after every placement that
+    /// no node holds more than `ceil((1 + epsilon) * average)` requests.
+    fn place_bounded<'a>(
+        ring: &ConsistHashRing,
+        epsilon: f64,
+        keys: impl Iterator<Item = &'a str>,
+    ) -> HashMap<String, usize> {
+        let mut loads: HashMap<String, usize> = HashMap::new();
+
+        for (placed, key) in keys.enumerate() {
+            let node = ring
+                .get_node_bounded(key, epsilon, |node| loads.get(node).copied().unwrap_or(0))
+                .unwrap()
+                .to_string();
+
+            *loads.entry(node).or_default() += 1;
+
+            let bound =
+                ((1.0 + epsilon) * (placed + 1) as f64 / ring.node_count() as f64).ceil() as usize;
+
+            for (node, load) in &loads {
+                assert!(
+                    *load <= bound,
+                    "{} holds {} requests, above the bound of {}",
+                    node,
+                    load,
+                    bound
+                );
+            }
+        }
+
+        loads
+    }
+
+    #[test]
+    fn bounded_load_spreads_a_hot_key() {
+        let ring = ring_with(8, 100);
+
+        let loads = place_bounded(&ring, 0.25, std::iter::repeat_n("hot", 1_000));
+
+        // 1000 requests at most 157 per node need at least seven nodes.
+        assert!(loads.len() >= 7);
+    }
+
+    #[test]
+    fn bounded_load_caps_skewed_keys() {
+        let ring = ring_with(10, 100);
+
+        // Most requests go to a handful of keys.
+        let keys: Vec<String> = (0..5_000)
+            .map(|request| match request % 5 {
+                0 => format!("key-{}", request),
+                _ => format!("hot-{}", request % 3),
+            })
+            .collect();
+
+        place_bounded(&ring, 0.1, keys.iter().map(String::as_str));
+    }
+
+    #[test]
+    fn bounded_load_keeps_owner_with_room() {
+        let ring = ring_with(10, 100);
+
+        for key in 0..1_000 {
+            let key = format!("key-{}", key);
+
+            assert_eq!(
+                ring.get_node_bounded(&key, 0.25, |_| 0),
+                ring.get_node(&key)
+            );
+        }
+    }
+
+    #[test]
+    fn adding_a_node_moves_about_one_nth_of_keys() {
+        let mut ring = ring_with(10, 100);
+
+        let before = owners(&ring);
+
+        ring.add_node("node-10");
+
+        let after = owners(&ring);
+
+        let moved: Vec<_> = before.iter().zip(&after).filter(|(b, a)| b != a).collect();
+
+        // Keys only move to the new node, and about K/N of them do.
+        assert!(moved.iter().all(|(_, after)| after.as_str() == "node-10"));
+
+        let expected = KEYS / 11;
+        assert!(
+            moved.len() > expected / 2 && moved.len() < expected * 3 / 2,
+            "{} keys moved, expected about {}",
+            moved.len(),
+            expected
+        );
+    }
+
+    #[test]
+    fn removing_a_node_moves_only_its_keys() {
+        let mut ring = ring_with(11, 100);
+
+        let before = owners(&ring);
+
+        ring.remove_node("node-3");
+
+        let after = owners(&ring);
+
+        for (before, after) in before.iter().zip(&after) {
+            assert_eq!(before == "node-3", before != after);
+        }
+
+        let moved = before.iter().filter(|owner| *owner == "node-3").count();
+
+        let expected = KEYS / 11;
+        assert!(
+            moved > expected / 2 && moved < expected * 3 / 2,
+            "{} keys moved, expected about {}",
+            moved,
+            expected
+        );
+    }
+
+    /// Hashes only what follows the node ID, so every node's `i`-th virtual
+    /// node lands on the same position.
+    fn colliding(key: &str) -> u64 {
+        let suffix = key.split_once('-').map_or(key, |(_, suffix)| suffix);
+
+        HashAlgorithm::XxHash64.hasher()(suffix)
+    }
+
+    fn layout(ring: &ConsistHashRing) -> Vec<(u64, String)> {
+        ring.ring
+            .iter()
+            .map(|hash| (*hash, ring.virt_map[hash].clone()))
+            .collect()
+    }
+
+    #[test]
+    fn layout_is_independent_of_insertion_order() {
+        let nodes: Vec<String> = (0..20).map(|node| format!("node-{}", node)).collect();
+
+        let mut forward = ConsistHashRing::new(50, HashAlgorithm::XxHash64.hasher());
+        forward.add_nodes(nodes.iter().map(String::as_str));
+
+        let mut backward = ConsistHashRing::new(50, HashAlgorithm::XxHash64.hasher());
+        for node in nodes.iter().rev() {
+            backward.add_node(node);
+        }
+
+        assert_eq!(layout(&forward), layout(&backward));
+    }
+
+    #[test]
+    fn add_and_remove_are_idempotent() {
+        let mut ring = ring_with(5, 50);
+        let expected = layout(&ring);
+
+        ring.add_node("node-2");
+        assert_eq!(layout(&ring), expected);
+
+        ring.remove_node("node-9");
+        assert_eq!(layout(&ring), expected);
+
+        ring.remove_node("node-2");
+        let removed = layout(&ring);
+
+        ring.remove_node("node-2");
+        assert_eq!(layout(&ring), removed);
+        assert_eq!(ring.node_count(), 4);
+
+        ring.add_node("node-2");
+        assert_eq!(layout(&ring), expected);
+    }
+
+    #[test]
+    fn colliding_virtual_nodes_resolve_by_node_id() {
+        let unprobed = colliding("0");
+
+        for order in [["a", "b"], ["b", "a"]] {
+            let mut ring = ConsistHashRing::new(4, colliding);
+            ring.add_nodes(order);
+
+            // Every position is taken once, the smaller ID keeps the
+            // unprobed ones.
+            assert_eq!(ring.len(), 8);
+            assert_eq!(ring.virt_map[&unprobed], "a");
+
+            ring.remove_node("a");
+
+            assert_eq!(ring.len(), 4);
+            assert_eq!(ring.virt_map[&unprobed], "b");
+        }
+    }
 }
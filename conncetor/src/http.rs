@@ -1,12 +1,18 @@
 use std::{net::SocketAddrV4, sync::Arc};
 
-use axum::{Router, http::StatusCode, response::IntoResponse, routing};
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+    routing,
+};
 use tokio::net::TcpListener;
 use tracing::debug;
 
 use tokio::sync::Notify;
 
-use crate::{service, state::AppState};
+use crate::{model::dto::AssignConnectorReq, service, state::AppState};
 
 async fn bind_addr(host: &str, port: u16) -> anyhow::Result<TcpListener> {
     let addr: SocketAddrV4 = format!("{}:{}", host, port)
@@ -26,16 +32,56 @@ async fn new_router(state: &AppState) -> anyhow::Result<Router> {
     let router: Router = Router::new()
         .route("/ws/{user_id}", routing::get(websock::on_websock_connect))
         .route("/check", routing::get(health_check))
+        .route("/ready", routing::get(readiness_check))
+        .route("/connect", routing::get(assign_connector))
         .with_state(state.clone());
 
     Ok(router)
 }
 
+/// Operator endpoints, only reachable through the admin listener.
+fn new_admin_router(state: &AppState) -> Router {
+    Router::new()
+        .route("/admin/upstreams", routing::get(list_upstreams))
+        .route("/metrics", routing::get(metrics))
+        .with_state(state.clone())
+}
+
 pub async fn run_server(state: &AppState, shutdown: Arc<Notify>) -> anyhow::Result<()> {
     let listener = bind_addr(state.config().http_host(), state.config().http_port()).await?;
 
+    let admin_listener = match state.config().admin_port() {
+        Some(port) => Some(bind_addr(state.config().admin_host(), port).await?),
+        None => {
+            debug!("No admin port configured, not serving the admin endpoints");
+
+            None
+        }
+    };
+
     let router = new_router(state).await?;
 
+    service::announce_websock_endpoint(state)
+        .await
+        .map_err(|err| anyhow::anyhow!("Error registering WebSocket endpoint: {}", err))?;
+
+    let admin_server = async {
+        match admin_listener {
+            Some(listener) => serve(listener, new_admin_router(state), shutdown.clone()).await,
+            None => Ok(()),
+        }
+    };
+
+    tokio::try_join!(serve(listener, router, shutdown.clone()), admin_server)?;
+
+    Ok(())
+}
+
+async fn serve(
+    listener: TcpListener,
+    router: Router,
+    shutdown: Arc<Notify>,
+) -> anyhow::Result<()> {
     axum::serve(listener, router.into_make_service())
         .with_graceful_shutdown(async move {
             debug!("HTTP server awaiting shutdown signal");
@@ -43,59 +89,156 @@ pub async fn run_server(state: &AppState, shutdown: Arc<Notify>) -> anyhow::Resu
             debug!("HTTP server received shutdown signal");
         })
         .await
-        .map_err(|err| anyhow::anyhow!("Error running server: {}", err))?;
-
-    Ok(())
+        .map_err(|err| anyhow::anyhow!("Error running server: {}", err))
 }
 
 // TODO: move it to an independent health module.
-async fn health_check() -> impl IntoResponse {
-    match service::health_check().await {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
+    match service::health_check(&state).await {
+        Ok(value) => (StatusCode::OK, Json(value)).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn readiness_check(State(state): State<AppState>) -> impl IntoResponse {
+    match service::readiness_check(&state).await {
+        Ok(value) => (
+            StatusCode::from_u16(value.code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            Json(value),
+        )
+            .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn assign_connector(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(args): Query<AssignConnectorReq>,
+) -> impl IntoResponse {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+
+    match service::assign_connector(args, authorization, &state).await {
+        Ok(value) => (StatusCode::OK, Json(value)).into_response(),
+        Err(err) => (err.status_code(), err.to_string()).into_response(),
     }
 }
 
+async fn list_upstreams(State(state): State<AppState>) -> impl IntoResponse {
+    match service::list_upstreams(&state).await {
+        Ok(value) => (StatusCode::OK, Json(value)).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        service::render_metrics(&state).await,
+    )
+}
+
 mod websock {
     use std::ops::ControlFlow;
 
     use axum::{
         body::Bytes,
         extract::{
-            Path, State,
+            Path, Query, State,
             ws::{Message, WebSocket, WebSocketUpgrade},
         },
-        response::IntoResponse,
+        http::StatusCode,
+        response::{IntoResponse, Response},
     };
     use crossfire::mpsc;
     use futures::{SinkExt as _, StreamExt as _};
-    use tracing::{debug, error, trace};
+    use serde::Deserialize;
+    use tracing::{debug, error, trace, warn};
 
     use crate::{
-        cache::CacheClient,
-        service::{handle_serv_message, handle_websock_message},
+        cache::{CacheClient, USER_CONNECTOR_KEY, connector_token},
+        service::{
+            Outbox, Session, handle_serv_message, handle_websock_message, take_offline,
+            verify_ticket,
+        },
         state::AppState,
     };
 
+    #[derive(Debug, Deserialize)]
+    pub struct UpgradeQuery {
+        ticket: Option<String>,
+    }
+
     pub async fn on_websock_connect(
         upgrade: WebSocketUpgrade,
         State(app_state): State<AppState>,
         Path(user_id): Path<String>,
-    ) -> impl IntoResponse {
+        Query(query): Query<UpgradeQuery>,
+    ) -> Response {
         debug!("Building websocket connection for user_id: {}", user_id);
 
+        if let Some(secret) = app_state.config().ticket_secret() {
+            let ticket = query.ticket.as_deref().unwrap_or_default();
+
+            if let Err(err) = verify_ticket(
+                secret,
+                ticket,
+                &user_id,
+                &app_state.config().websock_service_id(),
+            ) {
+                warn!("Refusing WebSocket upgrade for user_id {}: {}", user_id, err);
+
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+        }
+
+        // Without a ticket secret, the user ID is whatever the client claims.
+        let verified = app_state.config().ticket_secret().is_some();
+
         let app_state = app_state.clone();
         let user_id = user_id.clone();
 
-        upgrade.on_upgrade(move |socket| async move {
-            handle_websock_conn(socket, app_state, user_id).await;
-        })
+        upgrade
+            .on_upgrade(move |socket| async move {
+                handle_websock_conn(socket, app_state, user_id, verified).await;
+            })
+            .into_response()
+    }
+
+    /// Takes the messages stored for the user while it was offline.
+    async fn offline_messages(app_state: &AppState, user_id: &str) -> Vec<String> {
+        if app_state.config().offline_queue_len() == 0 {
+            return Vec::new();
+        }
+
+        match take_offline(app_state.cache(), user_id).await {
+            Ok(pending) => {
+                debug!(
+                    "Sending {} offline messages to user_id: {}",
+                    pending.len(),
+                    user_id
+                );
+
+                pending
+            }
+            Err(err) => {
+                warn!(
+                    "Error fetching offline messages for user_id {}: {}",
+                    user_id, err
+                );
+
+                Vec::new()
+            }
+        }
     }
 
     async fn handle_websock_conn(
         mut socket: axum::extract::ws::WebSocket,
         app_state: AppState,
         user_id: String,
+        verified: bool,
     ) {
         debug!("WebSocket connection established for user_id: {}", user_id);
 
@@ -134,7 +277,7 @@ mod websock {
 
         // NOTICE: Using bounded channel to prevent memory overflow in case of slow clients.
         // But it may drop messages if the client comsumes too slowly.
-        let (serv_tx, serv_rx) = mpsc::bounded_async(64);
+        let (serv_tx, serv_rx) = mpsc::bounded_async(app_state.config().user_queue_capacity());
 
         app_state
             .online_users()
@@ -142,19 +285,25 @@ mod websock {
             .or_insert(serv_tx.clone());
 
         let user_id_cloned = user_id.clone();
+        let app_state_clone = app_state.clone();
 
         let websock_send_task = tokio::spawn(async move {
-            while let Ok(serv_message) = serv_rx.recv().await {
-                if let Some(websock_message) = handle_serv_message(serv_message).await {
-                    if websock_snd.send(websock_message).await.is_err() {
-                        // If any error occurs, we assume the client has disconnected and break the loop.
-                        error!(
-                            "WebSocket send error for user_id: {}, disconnecting",
-                            &user_id_cloned
-                        );
-
-                        break;
-                    }
+            // Messages stored while the user was offline go out first, the
+            // live ones waiting in the queue until then.
+            let pending = offline_messages(&app_state_clone, &user_id_cloned).await;
+            let mut outbox = Outbox::new(pending, serv_rx);
+
+            while let Some(serv_message) = outbox.recv().await {
+                if let Some(websock_message) = handle_serv_message(serv_message).await
+                    && websock_snd.send(websock_message).await.is_err()
+                {
+                    // If any error occurs, we assume the client has disconnected and break the loop.
+                    error!(
+                        "WebSocket send error for user_id: {}, disconnecting",
+                        &user_id_cloned
+                    );
+
+                    break;
                 }
             }
 
@@ -166,6 +315,15 @@ mod websock {
 
         let user_id_cloned = user_id.clone();
         let user_serv_snd = serv_tx.clone();
+        let session = match verified {
+            true => Session::new(&user_id, app_state.config().service_id()).with_verified_user(),
+            false => Session::new(&user_id, app_state.config().service_id()),
+        };
+        debug!(
+            "Session {} started for user_id: {}",
+            session.session_id(),
+            user_id
+        );
 
         let app_state_clone = app_state.clone();
 
@@ -180,10 +338,11 @@ mod websock {
 
                         match handle_websock_message(
                             &user_id_cloned,
+                            &session,
                             &user_serv_snd,
-                            &app_state_clone.user_registry(),
-                            &app_state_clone.channel_registry(),
-                            &app_state_clone.message_registry(),
+                            app_state_clone.user_registry(),
+                            app_state_clone.channel_registry(),
+                            app_state_clone.message_registry(),
                             websock_message,
                         )
                         .await
@@ -281,12 +440,10 @@ mod websock {
         service_name: &str,
         service_id: &str,
     ) -> anyhow::Result<()> {
-        let server_token = format!("{}:{}", service_name, service_id);
-
-        const HASH_KEY: &str = "user:connector";
+        let server_token = connector_token(service_name, service_id);
 
         let result = cache
-            .hash_set(HASH_KEY, user_id, &server_token)
+            .hash_set(USER_CONNECTOR_KEY, user_id, &server_token)
             .await
             .map_err(|err| {
                 anyhow::anyhow!("Error registering user {} with cache: {}", user_id, err)
@@ -310,9 +467,7 @@ mod websock {
     }
 
     async fn deregister_user_online(cache: &CacheClient, user_id: &str) -> anyhow::Result<()> {
-        const HASH_KEY: &str = "user:connector";
-
-        let result = cache.hash_delete(HASH_KEY, user_id).await.map_err(|err| {
+        let result = cache.hash_delete(USER_CONNECTOR_KEY, user_id).await.map_err(|err| {
             anyhow::anyhow!("Error deregistering user {} from cache: {}", user_id, err)
         })?;
 
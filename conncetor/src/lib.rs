@@ -8,16 +8,30 @@ use tracing_subscriber::EnvFilter;
 
 mod cache;
 mod config;
-mod consist_hash;
+// Public for the benchmarks, not part of a supported API.
+#[doc(hidden)]
+pub mod consist_hash;
 mod http;
 mod message;
 mod model;
-mod registry;
+#[doc(hidden)]
+pub mod registry;
 mod rpc;
 mod service;
 mod state;
-
-use crate::{cache::CacheClient, config::AppConfig, registry::ConsulRegistry, state::AppState};
+mod tls;
+
+use crate::{
+    cache::CacheClient,
+    config::{AppConfig, DiscoveryConfig},
+    registry::{
+        ServiceRegistry,
+        discovery::{ConsulDiscovery, Discovery, DnsSrvDiscovery, StaticDiscovery},
+    },
+    service::RetryBudget,
+    state::AppState,
+    tls::ClientTls,
+};
 
 async fn init_env() -> anyhow::Result<()> {
     dotenvy::dotenv().map_err(|err| anyhow::anyhow!("Error when loading env: {}", err))?;
@@ -44,30 +58,72 @@ async fn init_config() -> anyhow::Result<AppConfig> {
     Ok(config)
 }
 
+fn init_discovery(config: &AppConfig) -> anyhow::Result<Arc<dyn Discovery>> {
+    let discovery: Arc<dyn Discovery> = match config.discovery() {
+        DiscoveryConfig::Consul => Arc::new(
+            ConsulDiscovery::new(config)
+                .map_err(|err| anyhow!("Error when initiating Consul discovery: {}", err))?,
+        ),
+        DiscoveryConfig::Static { services } => Arc::new(StaticDiscovery::new(services.clone())),
+        DiscoveryConfig::DnsSrv { domain } => Arc::new(
+            DnsSrvDiscovery::new(domain)
+                .map_err(|err| anyhow!("Error when initiating DNS SRV discovery: {}", err))?,
+        ),
+    };
+
+    debug!("Service discovery initialized: {:?}", discovery);
+
+    Ok(discovery)
+}
+
 async fn init_grpc_clients(
     config: &AppConfig,
+    discovery: &Arc<dyn Discovery>,
 ) -> anyhow::Result<(
-    ConsulRegistry<Channel>,
-    ConsulRegistry<Channel>,
-    ConsulRegistry<Channel>,
+    ServiceRegistry<Channel>,
+    ServiceRegistry<Channel>,
+    ServiceRegistry<Channel>,
 )> {
-    let consul_addr = format!("http://{}:{}", config.consul_host(), config.consul_port());
-
-    let user_resgitry = rpc::init_user_service(&consul_addr)
-        .await
-        .map_err(|err| anyhow!("Error when conecting to Consul user service: {}", err))?;
-    let channel_registry = rpc::init_channel_service(&consul_addr)
+    let retry_budget = Arc::new(RetryBudget::new(config.retry_budget().clone()));
+
+    let grpc_tls = config.grpc_tls();
+    let tls = grpc_tls
+        .client
+        .as_ref()
+        .map(|client_tls| ClientTls::new(client_tls, grpc_tls.reload_interval()))
+        .transpose()
+        .map_err(|err| anyhow!("Error when loading upstream TLS: {}", err))?;
+
+    let user_resgitry =
+        rpc::init_user_service(discovery.clone(), config, retry_budget.clone(), tls.clone())
+            .await
+            .map_err(|err| anyhow!("Error when conecting to user service: {}", err))?;
+    let channel_registry =
+        rpc::init_channel_service(discovery.clone(), config, retry_budget.clone(), tls.clone())
+            .await
+            .map_err(|err| anyhow!("Error when conecting to channel service: {}", err))?;
+    let message_registry = rpc::init_message_service(discovery.clone(), config, retry_budget, tls)
         .await
-        .map_err(|err| anyhow!("Error when conecting to Consul channel service: {}", err))?;
-    let message_registry = rpc::init_message_service(&consul_addr)
-        .await
-        .map_err(|err| anyhow!("Error when conecting to Consul message service: {}", err))?;
+        .map_err(|err| anyhow!("Error when conecting to message service: {}", err))?;
 
     debug!("gRPC clients initialized");
 
     Ok((user_resgitry, channel_registry, message_registry))
 }
 
+async fn init_connector_registry(
+    config: &AppConfig,
+    discovery: &Arc<dyn Discovery>,
+) -> anyhow::Result<ServiceRegistry<()>> {
+    let connector_registry = rpc::init_connector_registry(discovery.clone(), config)
+        .await
+        .map_err(|err| anyhow!("Error when discovering connectors: {}", err))?;
+
+    debug!("Connector registry initialized");
+
+    Ok(connector_registry)
+}
+
 async fn init_cache_client() -> anyhow::Result<CacheClient> {
     let cache_client = CacheClient::new()
         .await
@@ -81,16 +137,20 @@ async fn init_cache_client() -> anyhow::Result<CacheClient> {
 fn init_app_state(
     config: AppConfig,
     cache: CacheClient,
-    user_registry: ConsulRegistry<Channel>,
-    channel_registry: ConsulRegistry<Channel>,
-    message_registry: ConsulRegistry<Channel>,
+    discovery: Arc<dyn Discovery>,
+    user_registry: ServiceRegistry<Channel>,
+    channel_registry: ServiceRegistry<Channel>,
+    message_registry: ServiceRegistry<Channel>,
+    connector_registry: ServiceRegistry<()>,
 ) -> AppState {
     let state = AppState::new(
         config,
         cache,
+        discovery,
         user_registry,
         channel_registry,
         message_registry,
+        connector_registry,
     );
 
     debug!("AppState initialized");
@@ -123,14 +183,21 @@ pub async fn run() -> anyhow::Result<()> {
 
     let cache = init_cache_client().await?;
 
-    let (user_registry, channel_registry, message_registry) = init_grpc_clients(&config).await?;
+    let discovery = init_discovery(&config)?;
+
+    let (user_registry, channel_registry, message_registry) =
+        init_grpc_clients(&config, &discovery).await?;
+
+    let connector_registry = init_connector_registry(&config, &discovery).await?;
 
     let app_state = init_app_state(
         config.clone(),
         cache,
+        discovery,
         user_registry,
         channel_registry,
         message_registry,
+        connector_registry,
     );
 
     let shutdown = Arc::new(Notify::new());
@@ -139,10 +206,20 @@ pub async fn run() -> anyhow::Result<()> {
     let shutdown_for_grpc = shutdown.clone();
 
     let ctrlc_notify = shutdown.clone();
+    let ctrlc_state = app_state.clone();
 
     let ctrlc_task = tokio::spawn(async move {
         debug!("Shutdown listener waiting for CTRL-C");
         if tokio::signal::ctrl_c().await.is_ok() {
+            ctrlc_state.start_draining();
+
+            let drain_grace = ctrlc_state.config().drain_grace();
+
+            if !drain_grace.is_zero() {
+                debug!("CTRL-C received, draining for {:?}", drain_grace);
+                tokio::time::sleep(drain_grace).await;
+            }
+
             debug!("CTRL-C received, notifying shutdown listeners");
             ctrlc_notify.notify_waiters();
         } else {
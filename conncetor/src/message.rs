@@ -1,7 +1,9 @@
+use axum::extract::ws::Utf8Bytes;
+
 use crate::model::dto::DispatchedMessage;
 use crate::model::dto::{
-    CreateChannelRsp, CreateMessageRsp, GetUserInfoRsp, JoinChannelRsp, ListChannelDetailsRsp,
-    ListMessagesRsp, LoginUserRsp, RegisterUserRsp,
+    CreateChannelRsp, CreateMessageRsp, ErrorRsp, GetUserInfoRsp, JoinChannelRsp,
+    ListChannelDetailsRsp, ListMessagesRsp, LoginUserRsp, RegisterUserRsp,
 };
 
 #[derive(Debug)]
@@ -16,4 +18,7 @@ pub enum ServiceMessage {
     JoinChannelRsp(JoinChannelRsp),
     CreateMessageRsp(CreateMessageRsp),
     ListMessagesRsp(ListMessagesRsp),
+    ErrorRsp(ErrorRsp),
+    /// A response serialized once and shared by all its recipients.
+    Serialized(Utf8Bytes),
 }
@@ -125,10 +125,70 @@ pub mod dto {
         }
     }
 
-    mod rpc {
+    mod assign {
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Serialize, Deserialize)]
+        pub struct AssignConnectorReq {
+            pub user_id: String,
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        pub struct AssignConnectorRsp {
+            pub connector_id: String,
+            pub url: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub ticket: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub expires_at: Option<i64>,
+        }
+    }
+
+    mod admin {
+        use serde::Serialize;
+
+        use crate::registry::store::BreakerState;
+
+        #[derive(Debug, Serialize)]
+        pub struct UpstreamsRsp {
+            pub upstreams: Vec<UpstreamReport>,
+        }
+
+        #[derive(Debug, Serialize)]
+        pub struct UpstreamReport {
+            pub service: String,
+            pub degraded: bool,
+            pub stale: bool,
+            pub last_refresh_secs_ago: Option<u64>,
+            pub consecutive_refresh_errors: u32,
+            pub total_refresh_errors: u64,
+            pub instances: Vec<InstanceReport>,
+        }
+
+        #[derive(Debug, Serialize)]
+        pub struct InstanceReport {
+            pub id: String,
+            pub address: String,
+            pub in_flight: usize,
+            pub breaker: BreakerState,
+        }
+    }
+
+    mod error {
         use serde::{Deserialize, Serialize};
 
         #[derive(Debug, Serialize, Deserialize)]
+        pub struct ErrorRsp {
+            pub code: u16,
+            pub kind: String,
+            pub message: String,
+        }
+    }
+
+    mod rpc {
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Clone, Debug, Serialize, Deserialize)]
         pub struct DispatchedMessage {
             pub message_id: String,
             pub channel_id: String,
@@ -138,7 +198,10 @@ pub mod dto {
         }
     }
 
+    pub use admin::*;
+    pub use assign::*;
     pub use channel::*;
+    pub use error::*;
     pub use message::*;
     pub use rpc::*;
     pub use user::*;
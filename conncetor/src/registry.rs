@@ -1,105 +1,202 @@
 use std::{
+    collections::HashMap,
     fmt::Debug,
-    sync::{Arc, RwLock},
-    time::Duration,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
+use anyhow::anyhow;
+use arc_swap::{ArcSwap, Guard};
 use futures::future::join_all;
-use reqwest::{Client, Url};
-use tracing::{debug, warn};
-
-use crate::registry::{
-    model::{Registry, ServiceEntry},
-    store::{ConsistHashStore, ServiceData, Store},
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    config::{StalePolicy, UpstreamConfig},
+    registry::{
+        discovery::Discovery,
+        model::{InstanceFilter, Registry, ServiceEntry},
+        snapshot::RegistrySnapshot,
+        store::{BalancedStore, ServiceData, Store},
+    },
+    service::RetryBudget,
 };
 
+pub mod discovery;
 pub mod model;
+pub mod snapshot;
 pub mod store;
 
-pub struct ConsulRegistry<T, S = ConsistHashStore<T>>
+/// `ServiceRegistry` keeps a local store of the instances of one upstream
+/// service, refreshed periodically from a `Discovery` backend.
+///
+/// The store is published as an immutable snapshot: picks never wait on a
+/// lock, and a refresh builds the next store aside and swaps it in.
+pub struct ServiceRegistry<T, S = BalancedStore<T>>
 where
     T: Clone + Debug + Send + 'static,
-    S: Store<Extra = T> + Debug + Send + 'static,
+    S: Store<Extra = T> + Clone + Debug + Send + 'static,
 {
-    base_url: Url,
-    http_cli: Client,
+    discovery: Arc<dyn Discovery>,
     service_prefix: String,
-    store: Arc<RwLock<S>>,
+    upstream: Arc<UpstreamConfig>,
+    store: Arc<ArcSwap<S>>,
+    failures: Arc<Mutex<HashMap<String, ConnectBackoff>>>,
+    snapshot: Option<Arc<RegistrySnapshot>>,
+    /// Set while the store is served from the snapshot, until discovery
+    /// succeeds for the first time.
+    degraded: Arc<AtomicBool>,
+    status: Arc<Mutex<RefreshStatus>>,
+    retry_budget: Arc<RetryBudget>,
 }
 
-impl<T, S> ConsulRegistry<T, S>
+impl<T, S> ServiceRegistry<T, S>
 where
     T: Clone + Debug + Send + 'static,
-    S: Store<Extra = T> + Debug + Send + 'static,
+    S: Store<Extra = T> + Clone + Debug + Send + 'static,
 {
     const UPDATE_INTERVAL_SECS: u64 = 10;
 
-    pub fn new(consul_addr: &str, service_prefix: &str, store: S) -> anyhow::Result<Self> {
-        let consul_addr = Url::parse(consul_addr)?;
-
-        Ok(Self {
-            base_url: consul_addr,
-            http_cli: Client::new(),
+    pub fn new(discovery: Arc<dyn Discovery>, service_prefix: &str, store: S) -> Self {
+        Self {
+            discovery,
             service_prefix: service_prefix.to_string(),
-            store: Arc::new(RwLock::new(store)),
-        })
+            upstream: Arc::new(UpstreamConfig::default()),
+            store: Arc::new(ArcSwap::from_pointee(store)),
+            failures: Arc::new(Mutex::new(HashMap::new())),
+            snapshot: None,
+            degraded: Arc::new(AtomicBool::new(false)),
+            status: Arc::new(Mutex::new(RefreshStatus::new())),
+            retry_budget: Arc::new(RetryBudget::default()),
+        }
+    }
+
+    /// Applies the per upstream settings, e.g. only keeping the discovered
+    /// instances accepted by its filter.
+    pub fn with_upstream(mut self, upstream: UpstreamConfig) -> Self {
+        self.upstream = Arc::new(upstream);
+
+        self
+    }
+
+    pub fn upstream(&self) -> &UpstreamConfig {
+        &self.upstream
+    }
+
+    /// Persists every successfully discovered set of instances, and falls back
+    /// to the persisted one when discovery fails on startup.
+    pub fn with_snapshot(mut self, snapshot: RegistrySnapshot) -> Self {
+        self.snapshot = Some(Arc::new(snapshot));
+
+        self
+    }
+
+    /// Shares `retry_budget` with the other registries, for the retries of
+    /// all upstreams to be limited together.
+    pub fn with_retry_budget(mut self, retry_budget: Arc<RetryBudget>) -> Self {
+        self.retry_budget = retry_budget;
+
+        self
+    }
+
+    pub fn retry_budget(&self) -> &RetryBudget {
+        &self.retry_budget
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    pub fn service_prefix(&self) -> &str {
+        &self.service_prefix
+    }
+
+    pub fn refresh_status(&self) -> RefreshStatus {
+        self.status.lock().unwrap().clone()
     }
 
-    pub fn store(&self) -> &Arc<RwLock<S>> {
-        &self.store
+    /// Whether the store has not been refreshed successfully for longer than
+    /// the `stale_after_secs` of the upstream.
+    pub fn is_stale(&self) -> bool {
+        self.status
+            .lock()
+            .unwrap()
+            .is_stale(self.upstream.stale_after())
     }
 
+    /// The current snapshot of the store.
+    pub fn store(&self) -> Guard<Arc<S>> {
+        self.store.load()
+    }
+
+    /// Refreshes the store from discovery. Refreshes of one registry must not
+    /// overlap, which the task of `spawn_update_store` guarantees.
     pub async fn update_store<F, Fut>(&self, transformer: F) -> anyhow::Result<()>
     where
         F: Fn(ServiceEntry) -> Fut,
-        Fut: Future<Output = T> + Send,
+        Fut: Future<Output = anyhow::Result<T>> + Send,
     {
-        let client = self.http_cli.clone();
-
-        let url = self
-            .base_url
-            .join(&format!(
-                "/v1/health/service/{}?passing=true",
-                &self.service_prefix
-            ))
-            .map_err(|err| anyhow::anyhow!("Failed to construct URL: {}", err))?;
+        let services = match discover_services(
+            &*self.discovery,
+            &self.service_prefix,
+            self.upstream.filter(),
+        )
+        .await
+        {
+            Ok(services) => services,
+            Err(err) => {
+                self.status.lock().unwrap().record_error();
+
+                let Some(snapshot) = &self.snapshot else {
+                    return Err(err);
+                };
 
-        let res = client.get(url).send().await?;
+                let services = snapshot.load().await.map_err(|snapshot_err| {
+                    anyhow!("{}, and no usable snapshot: {}", err, snapshot_err)
+                })?;
 
-        let services: Vec<ServiceEntry> = res.json().await?;
+                warn!(
+                    "Failed to discover {} instances, starting degraded from the {} instances in {}: {}",
+                    self.service_prefix,
+                    services.len(),
+                    snapshot.path().display(),
+                    err
+                );
 
-        let datas = join_all(services.into_iter().map(|entry| {
-            let extra_data_fut = transformer(entry.clone());
+                self.degraded.store(true, Ordering::Relaxed);
 
-            async move {
-                let extra_data = extra_data_fut.await;
+                refresh_store(&self.store, &self.failures, services, &transformer).await;
 
-                ServiceData::new(entry, extra_data)
+                return Ok(());
             }
-        }))
-        .await;
+        };
+
+        save_snapshot(self.snapshot.as_deref(), &self.service_prefix, &services).await;
 
-        self.store.write().unwrap().update(datas);
+        refresh_store(&self.store, &self.failures, services, &transformer).await;
+
+        self.status.lock().unwrap().record_success();
+        self.degraded.store(false, Ordering::Relaxed);
 
         Ok(())
     }
 
     pub fn spawn_update_store<F, Fut>(&self, transformer: F) -> anyhow::Result<()>
     where
-        F: Fn(ServiceEntry) -> Fut + Send + 'static,
-        Fut: Future<Output = T> + Send,
+        F: Fn(ServiceEntry) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<T>> + Send,
     {
-        let client = self.http_cli.clone();
+        let discovery = self.discovery.clone();
+        let service_prefix = self.service_prefix.clone();
+        let upstream = self.upstream.clone();
 
         let store = self.store.clone();
-
-        let url = self
-            .base_url
-            .join(&format!(
-                "/v1/health/service/{}?passing=true",
-                &self.service_prefix
-            ))
-            .map_err(|err| anyhow::anyhow!("Failed to construct URL: {}", err))?;
+        let failures = self.failures.clone();
+        let snapshot = self.snapshot.clone();
+        let degraded = self.degraded.clone();
+        let status = self.status.clone();
 
         tokio::spawn(async move {
             let mut interval =
@@ -108,42 +205,51 @@ where
             loop {
                 interval.tick().await;
 
-                debug!("Updating service registry store from Consul: {}", url);
+                debug!("Updating service registry store for {}", service_prefix);
 
-                let res = match client.get(url.clone()).send().await {
-                    Ok(res) => res,
-                    Err(err) => {
-                        warn!("Failed to fetch services from Consul: {}", err);
-                        continue;
-                    }
-                };
-
-                let services: Vec<ServiceEntry> = match res.json().await {
+                let services = match discover_services(
+                    &*discovery,
+                    &service_prefix,
+                    upstream.filter(),
+                )
+                .await
+                {
                     Ok(services) => services,
                     Err(err) => {
-                        warn!("Failed to parse services from Consul response: {}", err);
+                        warn!("Failed to discover {} instances: {}", service_prefix, err);
+
+                        let became_stale = {
+                            let mut status = status.lock().unwrap();
+
+                            status.record_error();
+                            status.mark_stale(upstream.stale_after())
+                        };
+
+                        if became_stale {
+                            on_stale(&store, &status, &service_prefix, &upstream);
+                        }
+
                         continue;
                     }
                 };
 
-                let datas = join_all(services.into_iter().map(|entry| {
-                    let extra_data_fut = transformer(entry.clone());
-
-                    async move {
-                        let extra_data = extra_data_fut.await;
+                save_snapshot(snapshot.as_deref(), &service_prefix, &services).await;
 
-                        ServiceData::new(entry, extra_data)
-                    }
-                }))
-                .await;
+                refresh_store(&store, &failures, services, &transformer).await;
 
-                debug!(
-                    "Fetched {} services from Consul: {:#?}",
-                    datas.len(),
-                    &datas
-                );
+                if status.lock().unwrap().record_success() {
+                    info!(
+                        "{} registry refreshed again, it is not stale anymore",
+                        service_prefix
+                    );
+                }
 
-                store.write().unwrap().update(datas);
+                if degraded.swap(false, Ordering::Relaxed) {
+                    info!(
+                        "Discovered {} instances again, leaving degraded mode",
+                        service_prefix
+                    );
+                }
             }
         });
 
@@ -151,74 +257,449 @@ where
     }
 
     pub async fn register(&self, service: Registry, ttl: Duration) -> anyhow::Result<()> {
-        let url = self
-            .base_url
-            .join("/v1/agent/service/register")
-            .map_err(|err| anyhow::anyhow!("Failed to construct URL: {}", err))?;
-
-        let _ = self
-            .http_cli
-            .put(url)
-            .json(&service)
-            .send()
-            .await?
-            .error_for_status()?;
-
-        self.spawn_refresh_ttl(service.check().check_id().to_string(), ttl)
-            .await?;
+        self.discovery.register(service, ttl).await
+    }
+}
 
-        Ok(())
+async fn discover_services(
+    discovery: &dyn Discovery,
+    service_prefix: &str,
+    filter: &InstanceFilter,
+) -> anyhow::Result<Vec<ServiceEntry>> {
+    let mut services = discovery.discover(service_prefix).await?;
+
+    if !filter.is_empty() {
+        let total = services.len();
+
+        services.retain(|entry| filter.matches(entry));
+
+        debug!(
+            "Filter kept {} of {} {} instances",
+            services.len(),
+            total,
+            service_prefix
+        );
     }
 
-    async fn spawn_refresh_ttl(&self, check_id: String, ttl: Duration) -> anyhow::Result<()> {
-        let client = self.http_cli.clone();
+    Ok(services)
+}
 
-        let url = self
-            .base_url
-            .join(&format!("/v1/agent/check/update/{}", &check_id))
-            .map_err(|err| anyhow::anyhow!("Failed to construct URL: {}", err))?;
+/// Raises the alarm about a registry that just became stale, and applies the
+/// stale policy of the upstream to its store.
+fn on_stale<S>(
+    store: &ArcSwap<S>,
+    status: &Mutex<RefreshStatus>,
+    service_prefix: &str,
+    upstream: &UpstreamConfig,
+) where
+    S: Store + Clone,
+{
+    let status = status.lock().unwrap().clone();
+
+    error!(
+        "{} registry is stale: last refreshed {}, {} consecutive errors",
+        service_prefix,
+        status
+            .last_success()
+            .map_or("never".to_string(), |at| format!("{:?} ago", at.elapsed())),
+        status.consecutive_errors()
+    );
+
+    match upstream.stale_policy() {
+        StalePolicy::KeepLast => {}
+        StalePolicy::Empty => {
+            warn!(
+                "Emptying the {} store until the next successful refresh",
+                service_prefix
+            );
+
+            publish(store, |store| store.clear());
+        }
+    }
+}
 
-        tokio::spawn(async move {
-            let mut ttl_timer = tokio::time::interval(ttl / 2);
+async fn save_snapshot(
+    snapshot: Option<&RegistrySnapshot>,
+    service_prefix: &str,
+    services: &[ServiceEntry],
+) {
+    let Some(snapshot) = snapshot else {
+        return;
+    };
+
+    if let Err(err) = snapshot.save(service_prefix, services).await {
+        warn!(
+            "Failed to save {} snapshot to {}: {}",
+            service_prefix,
+            snapshot.path().display(),
+            err
+        );
+    }
+}
 
-            loop {
-                ttl_timer.tick().await;
+/// Outcome of the refreshes of a registry so far.
+#[derive(Clone, Debug)]
+pub struct RefreshStatus {
+    created_at: Instant,
+    last_success: Option<Instant>,
+    consecutive_errors: u32,
+    total_errors: u64,
+    /// Whether the staleness alarm was raised since the last success.
+    stale: bool,
+}
 
-                let body = serde_json::json!({
-                    "Status": "passing"
-                });
+impl RefreshStatus {
+    fn new() -> Self {
+        Self {
+            created_at: Instant::now(),
+            last_success: None,
+            consecutive_errors: 0,
+            total_errors: 0,
+            stale: false,
+        }
+    }
 
-                let res = match client.put(url.clone()).json(&body).send().await {
-                    Ok(res) => res,
-                    Err(err) => {
-                        warn!("Failed to send TTL update for check {}: {}", check_id, err);
-                        continue;
+    pub fn last_success(&self) -> Option<Instant> {
+        self.last_success
+    }
+
+    pub fn consecutive_errors(&self) -> u32 {
+        self.consecutive_errors
+    }
+
+    pub fn total_errors(&self) -> u64 {
+        self.total_errors
+    }
+
+    /// A registry that never refreshed successfully counts from its creation,
+    /// so one started from a snapshot is given the same grace period.
+    pub fn is_stale(&self, stale_after: Duration) -> bool {
+        self.last_success.unwrap_or(self.created_at).elapsed() > stale_after
+    }
+
+    /// Returns whether the registry was stale.
+    fn record_success(&mut self) -> bool {
+        self.last_success = Some(Instant::now());
+        self.consecutive_errors = 0;
+
+        std::mem::take(&mut self.stale)
+    }
+
+    fn record_error(&mut self) {
+        self.consecutive_errors += 1;
+        self.total_errors += 1;
+    }
+
+    /// Returns whether the registry just became stale.
+    fn mark_stale(&mut self, stale_after: Duration) -> bool {
+        if self.stale || !self.is_stale(stale_after) {
+            return false;
+        }
+
+        self.stale = true;
+
+        true
+    }
+}
+
+/// Backoff state of an instance whose extra data could not be built.
+#[derive(Debug)]
+struct ConnectBackoff {
+    attempts: u32,
+    retry_at: Instant,
+}
+
+impl ConnectBackoff {
+    const BASE_DELAY: Duration = Duration::from_secs(10);
+    const MAX_DELAY: Duration = Duration::from_secs(300);
+
+    fn next(prev: Option<&Self>) -> Self {
+        let attempts = prev.map_or(1, |backoff| backoff.attempts + 1);
+
+        let delay = Self::BASE_DELAY
+            .saturating_mul(1 << (attempts - 1).min(5))
+            .min(Self::MAX_DELAY);
+
+        Self {
+            attempts,
+            retry_at: Instant::now() + delay,
+        }
+    }
+}
+
+/// Diff the freshly fetched instances against the ones currently in `store`.
+///
+/// Instances whose ID and address are unchanged keep their existing extra data
+/// (e.g. an already connected gRPC channel), only new or moved instances go
+/// through `transformer`, and instances no longer reported are dropped along
+/// with their extra data.
+///
+/// Instances that `transformer` fails on are left out of the store and are not
+/// retried before their backoff in `failures` expires.
+async fn refresh_store<T, S, F, Fut>(
+    store: &ArcSwap<S>,
+    failures: &Mutex<HashMap<String, ConnectBackoff>>,
+    services: Vec<ServiceEntry>,
+    transformer: &F,
+) where
+    T: Clone + Debug + Send + 'static,
+    S: Store<Extra = T> + Clone,
+    F: Fn(ServiceEntry) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>> + Send,
+{
+    let mut current: HashMap<String, ServiceData<T>> = store
+        .load()
+        .list()
+        .into_iter()
+        .map(|data| (data.entry().info().id().to_string(), data))
+        .collect();
+
+    let mut kept = Vec::new();
+    let mut added = Vec::new();
+    let mut backing_off = 0;
+
+    let now = Instant::now();
+
+    {
+        let mut failures = failures.lock().unwrap();
+
+        // Forget the backoff of instances that are not reported anymore.
+        failures.retain(|id, _| services.iter().any(|entry| entry.info().id() == id));
+
+        for entry in services {
+            match current.remove(entry.info().id()) {
+                Some(data) if data.entry().info().address() == entry.info().address() => {
+                    kept.push(data.refreshed(entry));
+                }
+                _ => {
+                    if failures
+                        .get(entry.info().id())
+                        .is_some_and(|backoff| backoff.retry_at > now)
+                    {
+                        backing_off += 1;
+                    } else {
+                        added.push(entry);
                     }
-                };
+                }
+            }
+        }
+    }
+
+    let results = join_all(added.into_iter().map(|entry| {
+        let extra_data_fut = transformer(entry.clone());
+
+        async move { (entry, extra_data_fut.await) }
+    }))
+    .await;
+
+    let mut datas = Vec::with_capacity(kept.len() + results.len());
+
+    {
+        let mut failures = failures.lock().unwrap();
+
+        for (entry, result) in results {
+            let id = entry.info().id().to_string();
+
+            match result {
+                Ok(extra_data) => {
+                    failures.remove(&id);
+
+                    datas.push(ServiceData::new(entry, extra_data));
+                }
+                Err(err) => {
+                    let backoff = ConnectBackoff::next(failures.get(&id));
 
-                if let Err(err) = res.error_for_status() {
                     warn!(
-                        "Received error response for TTL update for check {}: {}",
-                        check_id, err
+                        "Excluding service instance {} at {} (attempt {}), retrying in {:?}: {}",
+                        id,
+                        entry.info().address(),
+                        backoff.attempts,
+                        backoff.retry_at - now,
+                        err
                     );
+
+                    failures.insert(id, backoff);
                 }
             }
-        });
-
-        Ok(())
+        }
     }
+
+    debug!(
+        "Refreshed services: {} kept, {} added, {} backing off, {} removed: {:?}",
+        kept.len(),
+        datas.len(),
+        backing_off,
+        current.len(),
+        current.keys().collect::<Vec<_>>()
+    );
+
+    datas.extend(kept);
+
+    // Removed instances are dropped here, which closes their channels once no
+    // in-flight request holds a clone anymore.
+    drop(current);
+
+    publish(store, |store| store.update(datas));
+}
+
+/// Applies `modify` to a copy of the current store and swaps the copy in.
+///
+/// Picks in flight keep using the snapshot they loaded. Writers are not
+/// synchronized with each other, which is fine as long as a registry is only
+/// written by its refresh task.
+fn publish<S>(store: &ArcSwap<S>, modify: impl FnOnce(&mut S))
+where
+    S: Store + Clone,
+{
+    let mut next = S::clone(&store.load());
+
+    modify(&mut next);
+
+    store.store(Arc::new(next));
 }
 
-impl<T, S> Debug for ConsulRegistry<T, S>
+impl<T, S> Debug for ServiceRegistry<T, S>
 where
     T: Clone + Debug + Send + 'static,
-    S: Store<Extra = T> + Debug + Send + 'static,
+    S: Store<Extra = T> + Clone + Debug + Send + 'static,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("ConsulClient")
-            .field("base_url", &self.base_url)
-            .field("http_cli", &self.http_cli)
+        f.debug_struct("ServiceRegistry")
+            .field("discovery", &self.discovery)
+            .field("service_prefix", &self.service_prefix)
             .field("store", &self.store)
+            .field("snapshot", &self.snapshot)
+            .field("degraded", &self.degraded)
+            .field("status", &self.status)
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::{
+        model::ServiceInfo,
+        store::{RoundRobinStore, ServiceData},
+    };
+
+    const STALE_AFTER: Duration = Duration::from_secs(30);
+
+    fn ago(secs: u64) -> Instant {
+        Instant::now() - Duration::from_secs(secs)
+    }
+
+    fn upstream(stale_policy: &str) -> UpstreamConfig {
+        serde_json::from_str(&format!(r#"{{"stale_policy": "{}"}}"#, stale_policy)).unwrap()
+    }
+
+    fn store() -> ArcSwap<RoundRobinStore<()>> {
+        let mut store = RoundRobinStore::new();
+
+        store.update(
+            (0..3)
+                .map(|ordinal| {
+                    let info = ServiceInfo::new(
+                        format!("instance-{}", ordinal),
+                        "upstream".to_string(),
+                        "127.0.0.1".to_string(),
+                        9000,
+                    );
+
+                    ServiceData::new(ServiceEntry::new(info), ())
+                })
+                .collect(),
+        );
+
+        ArcSwap::from_pointee(store)
+    }
+
+    fn stale_status() -> Mutex<RefreshStatus> {
+        let mut status = RefreshStatus::new();
+        status.created_at = ago(60);
+        status.record_error();
+
+        assert!(status.mark_stale(STALE_AFTER));
+
+        Mutex::new(status)
+    }
+
+    #[test]
+    fn new_status_is_fresh_during_the_grace_period() {
+        let mut status = RefreshStatus::new();
+
+        assert!(!status.is_stale(STALE_AFTER));
+        assert!(!status.mark_stale(STALE_AFTER));
+        assert!(status.last_success().is_none());
+    }
+
+    #[test]
+    fn never_refreshed_status_goes_stale_from_its_creation() {
+        let mut status = RefreshStatus::new();
+        status.created_at = ago(60);
+
+        assert!(status.is_stale(STALE_AFTER));
+    }
+
+    #[test]
+    fn status_goes_stale_and_fresh_again() {
+        let mut status = RefreshStatus::new();
+
+        assert!(!status.record_success());
+
+        status.last_success = Some(ago(60));
+        status.record_error();
+        status.record_error();
+
+        assert!(status.is_stale(STALE_AFTER));
+        // The alarm is raised once per stale period.
+        assert!(status.mark_stale(STALE_AFTER));
+        assert!(!status.mark_stale(STALE_AFTER));
+        assert_eq!(status.consecutive_errors(), 2);
+
+        assert!(status.record_success());
+        assert!(!status.is_stale(STALE_AFTER));
+        assert!(!status.mark_stale(STALE_AFTER));
+        assert_eq!(status.consecutive_errors(), 0);
+        assert_eq!(status.total_errors(), 2);
+
+        assert!(!status.record_success());
+
+        // A later outage raises the alarm again.
+        status.last_success = Some(ago(60));
+
+        assert!(status.mark_stale(STALE_AFTER));
+    }
+
+    #[test]
+    fn recent_success_outweighs_an_old_creation() {
+        let mut status = RefreshStatus::new();
+        status.created_at = ago(120);
+        status.last_success = Some(ago(10));
+
+        assert!(!status.is_stale(STALE_AFTER));
+    }
+
+    #[test]
+    fn keep_last_policy_keeps_the_instances() {
+        let store = store();
+
+        on_stale(&store, &stale_status(), "user", &upstream("keep_last"));
+
+        assert_eq!(store.load().list().len(), 3);
+        assert!(store.load().pick("key").is_some());
+    }
+
+    #[test]
+    fn empty_policy_clears_the_instances() {
+        let store = store();
+        let before = store.load_full();
+
+        on_stale(&store, &stale_status(), "user", &upstream("empty"));
+
+        assert!(store.load().list().is_empty());
+        assert!(store.load().pick("key").is_none());
+        // Picks in flight keep the snapshot they loaded.
+        assert_eq!(before.list().len(), 3);
+    }
+}
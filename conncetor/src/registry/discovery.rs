@@ -0,0 +1,27 @@
+use std::{fmt::Debug, time::Duration};
+
+use crate::registry::model::{Registry, ServiceEntry};
+
+mod consul;
+mod dns;
+mod fixed;
+
+pub use consul::ConsulDiscovery;
+pub use dns::DnsSrvDiscovery;
+pub use fixed::StaticDiscovery;
+
+/// `Discovery` abstracts the backend used to look up upstream instances and to
+/// announce this connector to others.
+#[tonic::async_trait]
+pub trait Discovery: Debug + Send + Sync {
+    /// Returns the currently healthy instances of `service`.
+    async fn discover(&self, service: &str) -> anyhow::Result<Vec<ServiceEntry>>;
+
+    /// Registers this process, keeping the registration alive for as long as
+    /// the backend requires it.
+    async fn register(&self, service: Registry, ttl: Duration) -> anyhow::Result<()>;
+
+    /// Updates an already registered service, e.g. with fresh metadata,
+    /// without starting another keepalive.
+    async fn reregister(&self, service: Registry) -> anyhow::Result<()>;
+}
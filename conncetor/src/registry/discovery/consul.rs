@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use reqwest::{
+    Certificate, Client, Identity, Url,
+    header::{HeaderMap, HeaderValue},
+};
+use tracing::warn;
+
+use crate::{
+    config::AppConfig,
+    registry::{
+        discovery::Discovery,
+        model::{Registry, ServiceEntry},
+    },
+};
+
+#[derive(Debug)]
+pub struct ConsulDiscovery {
+    base_url: Url,
+    http_cli: Client,
+    datacenter: Option<String>,
+    namespace: Option<String>,
+}
+
+impl ConsulDiscovery {
+    pub fn new(config: &AppConfig) -> anyhow::Result<Self> {
+        let scheme = if config.consul_https() {
+            "https"
+        } else {
+            "http"
+        };
+
+        let consul_addr = Url::parse(&format!(
+            "{}://{}:{}",
+            scheme,
+            config.consul_host(),
+            config.consul_port()
+        ))?;
+
+        Ok(Self {
+            base_url: consul_addr,
+            http_cli: Self::build_client(config)?,
+            datacenter: config.consul_datacenter().map(str::to_string),
+            namespace: config.consul_namespace().map(str::to_string),
+        })
+    }
+
+    fn build_client(config: &AppConfig) -> anyhow::Result<Client> {
+        let mut builder = Client::builder();
+
+        if let Some(token) = config.consul_token() {
+            let mut token = HeaderValue::from_str(token)
+                .map_err(|err| anyhow::anyhow!("Invalid Consul ACL token: {}", err))?;
+            token.set_sensitive(true);
+
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Consul-Token", token);
+
+            builder = builder.default_headers(headers);
+        }
+
+        if let Some(ca_path) = config.consul_ca_cert() {
+            let pem = std::fs::read(ca_path)
+                .map_err(|err| anyhow::anyhow!("Error reading Consul CA {}: {}", ca_path, err))?;
+
+            builder = builder.add_root_certificate(Certificate::from_pem(&pem)?);
+        }
+
+        match (config.consul_client_cert(), config.consul_client_key()) {
+            (Some(cert_path), Some(key_path)) => {
+                let cert = std::fs::read(cert_path).map_err(|err| {
+                    anyhow::anyhow!("Error reading Consul client cert {}: {}", cert_path, err)
+                })?;
+                let key = std::fs::read(key_path).map_err(|err| {
+                    anyhow::anyhow!("Error reading Consul client key {}: {}", key_path, err)
+                })?;
+
+                // The rustls backend reads the certificate chain and the key
+                // from a single PEM buffer.
+                let mut pem = cert;
+                pem.push(b'\n');
+                pem.extend_from_slice(&key);
+
+                builder = builder.identity(Identity::from_pem(&pem)?);
+            }
+            (None, None) => {}
+            _ => anyhow::bail!("Consul client cert and key must be configured together"),
+        }
+
+        Ok(builder.build()?)
+    }
+
+    /// Builds an API URL carrying the namespace, and the datacenter if the
+    /// endpoint is a catalog query rather than a local agent call.
+    fn api_url(&self, path: &str, with_datacenter: bool) -> anyhow::Result<Url> {
+        let mut url = self
+            .base_url
+            .join(path)
+            .map_err(|err| anyhow::anyhow!("Failed to construct URL: {}", err))?;
+
+        {
+            let mut query = url.query_pairs_mut();
+
+            if let Some(datacenter) = self.datacenter.as_deref().filter(|_| with_datacenter) {
+                query.append_pair("dc", datacenter);
+            }
+
+            if let Some(namespace) = &self.namespace {
+                query.append_pair("ns", namespace);
+            }
+        }
+
+        // Avoid a dangling `?` when no parameter was appended.
+        if url.query() == Some("") {
+            url.set_query(None);
+        }
+
+        Ok(url)
+    }
+
+    async fn spawn_refresh_ttl(&self, check_id: String, ttl: Duration) -> anyhow::Result<()> {
+        let client = self.http_cli.clone();
+
+        let url = self.api_url(&format!("/v1/agent/check/update/{}", &check_id), false)?;
+
+        tokio::spawn(async move {
+            let mut ttl_timer = tokio::time::interval(ttl / 2);
+
+            loop {
+                ttl_timer.tick().await;
+
+                let body = serde_json::json!({
+                    "Status": "passing"
+                });
+
+                let res = match client.put(url.clone()).json(&body).send().await {
+                    Ok(res) => res,
+                    Err(err) => {
+                        warn!("Failed to send TTL update for check {}: {}", check_id, err);
+                        continue;
+                    }
+                };
+
+                if let Err(err) = res.error_for_status() {
+                    warn!(
+                        "Received error response for TTL update for check {}: {}",
+                        check_id, err
+                    );
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Discovery for ConsulDiscovery {
+    async fn discover(&self, service: &str) -> anyhow::Result<Vec<ServiceEntry>> {
+        let mut url = self.api_url(&format!("/v1/health/service/{}", service), true)?;
+
+        url.query_pairs_mut().append_pair("passing", "true");
+
+        // An ACL or namespace problem comes back as an error status with a
+        // plain text body, which would otherwise surface as a JSON error.
+        let res = self.http_cli.get(url).send().await?.error_for_status()?;
+
+        let services: Vec<ServiceEntry> = res.json().await?;
+
+        Ok(services)
+    }
+
+    async fn register(&self, service: Registry, ttl: Duration) -> anyhow::Result<()> {
+        let check_id = service.check().check_id().to_string();
+        let has_ttl = service.check().ttl().is_some();
+
+        self.reregister(service).await?;
+
+        // Other checks are run by Consul itself.
+        if has_ttl {
+            self.spawn_refresh_ttl(check_id, ttl).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn reregister(&self, service: Registry) -> anyhow::Result<()> {
+        // Registering an existing service ID again replaces its definition.
+        let url = self.api_url("/v1/agent/service/register", false)?;
+
+        let _ = self
+            .http_cli
+            .put(url)
+            .json(&service)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
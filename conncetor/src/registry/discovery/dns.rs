@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use hickory_resolver::TokioResolver;
+use tracing::debug;
+
+use crate::registry::{
+    discovery::Discovery,
+    model::{Registry, ServiceEntry, ServiceInfo},
+};
+
+/// `DnsSrvDiscovery` resolves instances from `_<service>._tcp.<domain>` SRV
+/// records, e.g. the ones served by Consul DNS or a Kubernetes headless service.
+#[derive(Debug)]
+pub struct DnsSrvDiscovery {
+    resolver: TokioResolver,
+    domain: String,
+}
+
+impl DnsSrvDiscovery {
+    pub fn new(domain: &str) -> anyhow::Result<Self> {
+        let resolver = TokioResolver::builder_tokio()
+            .map_err(|err| anyhow::anyhow!("Failed to read system DNS config: {}", err))?
+            .build();
+
+        Ok(Self {
+            resolver,
+            domain: domain.trim_matches('.').to_string(),
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Discovery for DnsSrvDiscovery {
+    async fn discover(&self, service: &str) -> anyhow::Result<Vec<ServiceEntry>> {
+        let name = format!("_{}._tcp.{}.", service, self.domain);
+
+        let lookup = self.resolver.srv_lookup(name.as_str()).await?;
+
+        let entries = lookup
+            .iter()
+            .map(|srv| {
+                let target = srv.target().to_utf8();
+                let target = target.trim_end_matches('.');
+
+                ServiceEntry::new(ServiceInfo::new(
+                    format!("{}:{}", target, srv.port()),
+                    service.to_string(),
+                    target.to_string(),
+                    srv.port(),
+                ))
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    async fn register(&self, service: Registry, _ttl: Duration) -> anyhow::Result<()> {
+        debug!(
+            "DNS SRV discovery in use, skipping registration of {}",
+            service.id()
+        );
+
+        Ok(())
+    }
+
+    async fn reregister(&self, _service: Registry) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
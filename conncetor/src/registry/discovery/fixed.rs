@@ -0,0 +1,67 @@
+use std::{collections::HashMap, time::Duration};
+
+use tracing::debug;
+
+use crate::{
+    config::StaticInstance,
+    registry::{
+        discovery::Discovery,
+        model::{Registry, ServiceEntry, ServiceInfo},
+    },
+};
+
+/// `StaticDiscovery` serves a fixed list of instances taken from the config
+/// file, which is handy to run the connector without a Consul agent.
+#[derive(Debug)]
+pub struct StaticDiscovery {
+    services: HashMap<String, Vec<StaticInstance>>,
+}
+
+impl StaticDiscovery {
+    pub fn new(services: HashMap<String, Vec<StaticInstance>>) -> Self {
+        Self { services }
+    }
+}
+
+#[tonic::async_trait]
+impl Discovery for StaticDiscovery {
+    async fn discover(&self, service: &str) -> anyhow::Result<Vec<ServiceEntry>> {
+        let entries = self
+            .services
+            .get(service)
+            .map(|instances| {
+                instances
+                    .iter()
+                    .map(|instance| {
+                        ServiceEntry::new(
+                            ServiceInfo::new(
+                                instance.id.clone(),
+                                service.to_string(),
+                                instance.address.clone(),
+                                instance.port,
+                            )
+                            .with_tags(instance.tags.clone())
+                            .with_meta(instance.meta.clone())
+                            .with_weight(instance.weight.unwrap_or(1)),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(entries)
+    }
+
+    async fn register(&self, service: Registry, _ttl: Duration) -> anyhow::Result<()> {
+        debug!(
+            "Static discovery in use, skipping registration of {}",
+            service.id()
+        );
+
+        Ok(())
+    }
+
+    async fn reregister(&self, _service: Registry) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
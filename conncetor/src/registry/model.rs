@@ -1,16 +1,31 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
-use serde::{Deserialize, Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ServiceEntry {
+    #[serde(default)]
+    node: NodeInfo,
     service: ServiceInfo,
 }
 
 impl ServiceEntry {
     pub fn new(service: ServiceInfo) -> Self {
-        Self { service }
+        Self {
+            node: NodeInfo::default(),
+            service,
+        }
+    }
+
+    pub fn with_node(mut self, node: NodeInfo) -> Self {
+        self.node = node;
+
+        self
+    }
+
+    pub fn node(&self) -> &NodeInfo {
+        &self.node
     }
 
     pub fn info(&self) -> &ServiceInfo {
@@ -30,6 +45,12 @@ pub struct ServiceInfo {
     service: String,
     address: String,
     port: u16,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    tags: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    meta: HashMap<String, String>,
+    #[serde(default)]
+    weights: Weights,
 }
 
 impl ServiceInfo {
@@ -39,9 +60,30 @@ impl ServiceInfo {
             service,
             address,
             port,
+            tags: Vec::new(),
+            meta: HashMap::new(),
+            weights: Weights::default(),
         }
     }
 
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+
+        self
+    }
+
+    pub fn with_meta(mut self, meta: HashMap<String, String>) -> Self {
+        self.meta = meta;
+
+        self
+    }
+
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weights.passing = weight;
+
+        self
+    }
+
     pub fn id(&self) -> &str {
         &self.id
     }
@@ -53,29 +95,182 @@ impl ServiceInfo {
     pub fn name(&self) -> &str {
         &self.service
     }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn meta(&self) -> &HashMap<String, String> {
+        &self.meta
+    }
+
+    /// Relative weight of a passing instance, never zero.
+    pub fn weight(&self) -> u32 {
+        self.weights.passing.max(1)
+    }
+}
+
+/// Consul service weights, applied depending on the health check state.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Weights {
+    passing: u32,
+    warning: u32,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            passing: 1,
+            warning: 1,
+        }
+    }
+}
+
+/// The Consul node an instance runs on.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NodeInfo {
+    #[serde(rename = "Node", default)]
+    name: String,
+    #[serde(default)]
+    address: String,
+    #[serde(default)]
+    datacenter: String,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    meta: HashMap<String, String>,
+}
+
+impl NodeInfo {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn datacenter(&self) -> &str {
+        &self.datacenter
+    }
+
+    pub fn meta(&self) -> &HashMap<String, String> {
+        &self.meta
+    }
+}
+
+/// Selects the instances of an upstream service a connector may route to,
+/// e.g. only the canary release or only the local zone.
+///
+/// Every listed tag and every listed key/value pair must be present.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct InstanceFilter {
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    meta: HashMap<String, String>,
+    #[serde(default)]
+    node_meta: HashMap<String, String>,
+}
+
+impl InstanceFilter {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.meta.is_empty() && self.node_meta.is_empty()
+    }
+
+    pub fn matches(&self, entry: &ServiceEntry) -> bool {
+        let contains_all = |expected: &HashMap<String, String>,
+                            actual: &HashMap<String, String>| {
+            expected
+                .iter()
+                .all(|(key, value)| actual.get(key) == Some(value))
+        };
+
+        self.tags
+            .iter()
+            .all(|tag| entry.info().tags().contains(tag))
+            && contains_all(&self.meta, entry.info().meta())
+            && contains_all(&self.node_meta, entry.node().meta())
+    }
+}
+
+/// Consul reports missing tags and metadata as `null` rather than leaving them
+/// out, so fall back to the default value in both cases.
+fn deserialize_nullable<'de, D, V>(deserializer: D) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Default + Deserialize<'de>,
+{
+    Ok(Option::<V>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct HeathCheck {
-    #[serde(rename = "TTL", serialize_with = "serialize_ttl")]
-    pub ttl: Duration,
+    #[serde(
+        rename = "TTL",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_secs"
+    )]
+    pub ttl: Option<Duration>,
+    /// `host:port` of a gRPC health service Consul probes every `interval`.
+    #[serde(rename = "GRPC", skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<String>,
+    #[serde(rename = "GRPCUseTLS", skip_serializing_if = "Option::is_none")]
+    pub grpc_use_tls: Option<bool>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_secs"
+    )]
+    pub interval: Option<Duration>,
     #[serde(rename = "CheckID")]
     pub check_id: String,
     pub name: String,
+    /// Initial status of the check, Consul starts it as critical if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
 }
 
 impl HeathCheck {
     pub fn new(ttl: Duration, check_id: String, name: String) -> Self {
         Self {
-            ttl,
+            ttl: Some(ttl),
+            grpc: None,
+            grpc_use_tls: None,
+            interval: None,
             check_id,
             name,
+            status: None,
         }
     }
 
-    pub fn ttl(&self) -> &Duration {
-        &self.ttl
+    pub fn grpc(target: String, interval: Duration, check_id: String, name: String) -> Self {
+        Self {
+            ttl: None,
+            grpc: Some(target),
+            grpc_use_tls: None,
+            interval: Some(interval),
+            check_id,
+            name,
+            status: None,
+        }
+    }
+
+    pub fn with_tls(mut self, use_tls: bool) -> Self {
+        self.grpc_use_tls = Some(use_tls);
+
+        self
+    }
+
+    pub fn with_status(mut self, status: &str) -> Self {
+        self.status = Some(status.to_string());
+
+        self
+    }
+
+    /// Set for checks kept passing by the registered service itself.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
     }
 
     pub fn check_id(&self) -> &str {
@@ -87,13 +282,15 @@ impl HeathCheck {
     }
 }
 
-fn serialize_ttl<S>(d: &Duration, s: S) -> Result<S::Ok, S::Error>
+fn serialize_secs<S>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    // Consul expects TTL as a string like "300s"
-    let s_val = format!("{}s", d.as_secs());
-    s.serialize_str(&s_val)
+    // Consul expects durations as a string like "300s"
+    match d {
+        Some(d) => s.serialize_str(&format!("{}s", d.as_secs())),
+        None => s.serialize_none(),
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -104,6 +301,10 @@ pub struct Registry {
     name: String,
     address: String,
     port: u16,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    meta: HashMap<String, String>,
     check: HeathCheck,
 }
 
@@ -114,10 +315,24 @@ impl Registry {
             name,
             address,
             port,
+            tags: Vec::new(),
+            meta: HashMap::new(),
             check,
         }
     }
 
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+
+        self
+    }
+
+    pub fn with_meta(mut self, meta: HashMap<String, String>) -> Self {
+        self.meta = meta;
+
+        self
+    }
+
     pub fn id(&self) -> &str {
         &self.id
     }
@@ -134,7 +349,142 @@ impl Registry {
         self.port
     }
 
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn meta(&self) -> &HashMap<String, String> {
+        &self.meta
+    }
+
     pub fn check(&self) -> &HeathCheck {
         &self.check
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(json: &str) -> InstanceFilter {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn entry() -> ServiceEntry {
+        let node: NodeInfo =
+            serde_json::from_str(r#"{"Node": "node-1", "Meta": {"zone": "a"}}"#).unwrap();
+
+        ServiceEntry::new(
+            ServiceInfo::new(
+                "user-1".to_string(),
+                "user".to_string(),
+                "127.0.0.1".to_string(),
+                9000,
+            )
+            .with_tags(vec!["canary".to_string(), "v2".to_string()])
+            .with_meta(HashMap::from([("version".to_string(), "2".to_string())])),
+        )
+        .with_node(node)
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = filter("{}");
+
+        assert!(filter.is_empty());
+        assert!(filter.matches(&entry()));
+    }
+
+    #[test]
+    fn filter_requires_every_tag() {
+        assert!(filter(r#"{"tags": ["canary"]}"#).matches(&entry()));
+        assert!(filter(r#"{"tags": ["canary", "v2"]}"#).matches(&entry()));
+        assert!(!filter(r#"{"tags": ["canary", "v3"]}"#).matches(&entry()));
+    }
+
+    #[test]
+    fn filter_requires_every_meta_pair() {
+        assert!(filter(r#"{"meta": {"version": "2"}}"#).matches(&entry()));
+        assert!(!filter(r#"{"meta": {"version": "1"}}"#).matches(&entry()));
+        assert!(!filter(r#"{"meta": {"version": "2", "env": "prod"}}"#).matches(&entry()));
+    }
+
+    #[test]
+    fn filter_requires_every_node_meta_pair() {
+        assert!(filter(r#"{"node_meta": {"zone": "a"}}"#).matches(&entry()));
+        assert!(!filter(r#"{"node_meta": {"zone": "b"}}"#).matches(&entry()));
+        // Service metadata does not satisfy node metadata and vice versa.
+        assert!(!filter(r#"{"node_meta": {"version": "2"}}"#).matches(&entry()));
+        assert!(!filter(r#"{"meta": {"zone": "a"}}"#).matches(&entry()));
+    }
+
+    #[test]
+    fn filter_combines_all_conditions() {
+        let filter =
+            filter(r#"{"tags": ["canary"], "meta": {"version": "2"}, "node_meta": {"zone": "b"}}"#);
+
+        assert!(!filter.matches(&entry()));
+    }
+
+    #[test]
+    fn deserializes_null_tags_and_meta() {
+        let json = r#"{
+            "Node": {"Node": "node-1", "Address": "10.0.0.1", "Meta": null},
+            "Service": {
+                "ID": "user-1",
+                "Service": "user",
+                "Address": "10.0.0.1",
+                "Port": 9000,
+                "Tags": null,
+                "Meta": null
+            }
+        }"#;
+
+        let entry: ServiceEntry = serde_json::from_str(json).unwrap();
+
+        assert!(entry.info().tags().is_empty());
+        assert!(entry.info().meta().is_empty());
+        assert!(entry.node().meta().is_empty());
+        assert_eq!(entry.node().name(), "node-1");
+        assert_eq!(entry.info().weight(), 1);
+    }
+
+    #[test]
+    fn deserializes_missing_node_and_fields() {
+        let json = r#"{
+            "Service": {"ID": "user-1", "Service": "user", "Address": "10.0.0.1", "Port": 9000}
+        }"#;
+
+        let entry: ServiceEntry = serde_json::from_str(json).unwrap();
+
+        assert!(entry.info().tags().is_empty());
+        assert!(entry.info().meta().is_empty());
+        assert!(entry.node().meta().is_empty());
+        assert!(!filter(r#"{"node_meta": {"zone": "a"}}"#).matches(&entry));
+    }
+
+    #[test]
+    fn deserializes_present_tags_and_meta() {
+        let json = r#"{
+            "Node": {"Node": "node-1", "Meta": {"zone": "a"}},
+            "Service": {
+                "ID": "user-1",
+                "Service": "user",
+                "Address": "10.0.0.1",
+                "Port": 9000,
+                "Tags": ["canary"],
+                "Meta": {"version": "2"},
+                "Weights": {"Passing": 5, "Warning": 1}
+            }
+        }"#;
+
+        let entry: ServiceEntry = serde_json::from_str(json).unwrap();
+
+        assert_eq!(entry.info().tags(), ["canary".to_string()]);
+        assert_eq!(entry.info().weight(), 5);
+        assert!(
+            filter(r#"{"tags": ["canary"], "meta": {"version": "2"}, "node_meta": {"zone": "a"}}"#)
+                .matches(&entry)
+        );
+    }
+}
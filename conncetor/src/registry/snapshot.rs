@@ -0,0 +1,100 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::registry::model::ServiceEntry;
+
+/// `RegistrySnapshot` persists the last discovered instances of one upstream
+/// service, so the connector can still start when discovery is unreachable.
+#[derive(Debug)]
+pub struct RegistrySnapshot {
+    path: PathBuf,
+    /// The last content written, to skip rewriting an unchanged file on every
+    /// refresh.
+    last_saved: Mutex<Option<Vec<u8>>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    service: String,
+    saved_at: u64,
+    entries: Vec<ServiceEntry>,
+}
+
+impl RegistrySnapshot {
+    pub fn new(dir: impl AsRef<Path>, service: &str) -> Self {
+        Self {
+            path: dir.as_ref().join(format!("{}.json", service)),
+            last_saved: Mutex::new(None),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn load(&self) -> anyhow::Result<Vec<ServiceEntry>> {
+        let content = tokio::fs::read(&self.path)
+            .await
+            .map_err(|err| anyhow!("Error when reading {}: {}", self.path.display(), err))?;
+
+        let snapshot: SnapshotFile = serde_json::from_slice(&content)
+            .map_err(|err| anyhow!("Error when parsing {}: {}", self.path.display(), err))?;
+
+        debug!(
+            "Loaded {} {} instances saved at {} from {}",
+            snapshot.entries.len(),
+            snapshot.service,
+            snapshot.saved_at,
+            self.path.display()
+        );
+
+        Ok(snapshot.entries)
+    }
+
+    /// Writes `entries` to a temporary file first and renames it over the
+    /// snapshot, so a crash never leaves a truncated snapshot behind.
+    pub async fn save(&self, service: &str, entries: &[ServiceEntry]) -> anyhow::Result<()> {
+        let content = serde_json::to_vec(entries)?;
+
+        if self.last_saved.lock().unwrap().as_ref() == Some(&content) {
+            return Ok(());
+        }
+
+        let saved_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+
+        let file = serde_json::to_vec_pretty(&SnapshotFile {
+            service: service.to_string(),
+            saved_at,
+            entries: entries.to_vec(),
+        })?;
+
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+
+        let tmp_path = self.path.with_extension("json.tmp");
+
+        tokio::fs::write(&tmp_path, file).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+
+        debug!(
+            "Saved {} {} instances to {}",
+            entries.len(),
+            service,
+            self.path.display()
+        );
+
+        *self.last_saved.lock().unwrap() = Some(content);
+
+        Ok(())
+    }
+}
@@ -1,10 +1,28 @@
-use std::{collections::HashMap, fmt::Debug, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use serde::Deserialize;
+use tracing::debug;
 
 use crate::{
-    consist_hash::{ConsistHashRing, Hasher},
+    consist_hash::{ConsistHashRing, Hasher, RingDistribution},
     registry::model::ServiceEntry,
 };
 
+mod affinity;
+mod balance;
+mod breaker;
+
+pub use affinity::{JumpHashStore, RendezvousStore};
+pub use balance::{LeastRequestsStore, P2cStore, RoundRobinStore, WeightedRandomStore};
+pub use breaker::{BreakerConfig, BreakerState, CircuitBreaker};
+
 /// `ServiceData` encapsulates a service instance along with its associated extra data.
 #[derive(Clone, Debug)]
 pub struct ServiceData<T>
@@ -13,6 +31,9 @@ where
 {
     entry: Arc<ServiceEntry>,
     extra_data: T,
+    /// Number of requests currently outstanding on this instance.
+    in_flight: Arc<AtomicUsize>,
+    breaker: Arc<CircuitBreaker>,
 }
 
 impl<T> ServiceData<T>
@@ -23,6 +44,19 @@ where
         Self {
             entry: Arc::new(instance),
             extra_data,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            breaker: Arc::new(CircuitBreaker::default()),
+        }
+    }
+
+    /// Returns a copy carrying the latest `instance` description while keeping
+    /// the existing extra data, request tracking and circuit breaker.
+    pub fn refreshed(&self, instance: ServiceEntry) -> Self {
+        Self {
+            entry: Arc::new(instance),
+            extra_data: self.extra_data.clone(),
+            in_flight: self.in_flight.clone(),
+            breaker: self.breaker.clone(),
         }
     }
 
@@ -33,18 +67,97 @@ where
     pub fn extra_data(&self) -> &T {
         &self.extra_data
     }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    /// Whether calls may be sent to this instance, i.e. its circuit breaker is
+    /// not open.
+    pub fn is_available(&self) -> bool {
+        self.breaker.is_available()
+    }
+
+    /// Counts a request as outstanding on this instance until the returned
+    /// guard is dropped.
+    pub fn track(&self) -> InFlightGuard {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+
+        InFlightGuard(self.in_flight.clone())
+    }
 }
 
+#[derive(Debug)]
+pub struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Selects the instances a store may route to.
+pub type Eligible<'a, T> = &'a dyn Fn(&ServiceData<T>) -> bool;
+
+/// A load-balancing strategy over the instances of a service.
+///
+/// Strategies implement `pick_where` and `candidates_where`, while `pick` and
+/// `candidates` are shared by all of them and leave out the instances whose
+/// circuit breaker is open.
 pub trait Store: Send + Sync {
     type Extra: Clone + Debug + Send;
 
-    fn pick(&self, key: &str) -> Option<ServiceData<Self::Extra>>;
+    /// Picks the instance for `key` among the `eligible` ones.
+    fn pick_where(
+        &self,
+        key: &str,
+        eligible: Eligible<Self::Extra>,
+    ) -> Option<ServiceData<Self::Extra>>;
+
+    /// Returns up to `count` distinct `eligible` instances to try in order for
+    /// `key`, starting with the one `pick_where` would return.
+    fn candidates_where(
+        &self,
+        key: &str,
+        count: usize,
+        eligible: Eligible<Self::Extra>,
+    ) -> Vec<ServiceData<Self::Extra>> {
+        let Some(first) = self.pick_where(key, eligible) else {
+            return Vec::new();
+        };
+
+        let first_id = first.entry().info().id().to_string();
+
+        let others = self
+            .list()
+            .into_iter()
+            .filter(|data| data.entry().info().id() != first_id && eligible(data));
+
+        std::iter::once(first).chain(others).take(count).collect()
+    }
+
+    /// Picks the instance for `key`, routing around open circuit breakers.
+    fn pick(&self, key: &str) -> Option<ServiceData<Self::Extra>> {
+        self.pick_where(key, &ServiceData::is_available)
+    }
+
+    /// Returns up to `count` distinct instances to try in order for `key`,
+    /// starting with the one `pick` would return and skipping open circuit
+    /// breakers.
+    fn candidates(&self, key: &str, count: usize) -> Vec<ServiceData<Self::Extra>> {
+        self.candidates_where(key, count, &ServiceData::is_available)
+    }
+
     fn list(&self) -> Vec<ServiceData<Self::Extra>>;
     fn update(&mut self, datas: Vec<ServiceData<Self::Extra>>);
     fn clear(&mut self);
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ConsistHashStore<T>
 where
     T: Clone + Debug + Send + Sync,
@@ -52,6 +165,8 @@ where
     ring: ConsistHashRing,
     replicas: usize,
     hasher: Hasher,
+    /// The `epsilon` of bounded-load hashing, or `None` for the plain ring.
+    load_factor: Option<f64>,
 
     instances: HashMap<String, ServiceData<T>>,
 }
@@ -65,9 +180,31 @@ where
             replicas,
             hasher,
             ring: ConsistHashRing::new(replicas, hasher),
+            load_factor: None,
             instances: HashMap::new(),
         }
     }
+
+    /// Caps each instance at `(1 + epsilon)` times the average number of
+    /// in-flight requests, see `ConsistHashRing::get_node_bounded`.
+    ///
+    /// Panics unless `epsilon` is a positive finite number, which the
+    /// configuration already guarantees.
+    pub fn with_bounded_load(mut self, epsilon: f64) -> Self {
+        assert!(
+            epsilon.is_finite() && epsilon > 0.0,
+            "Bounded load factor must be positive, got {}",
+            epsilon
+        );
+
+        self.load_factor = Some(epsilon);
+
+        self
+    }
+
+    pub fn distribution(&self) -> RingDistribution {
+        self.ring.distribution()
+    }
 }
 
 impl<T> Store for ConsistHashStore<T>
@@ -76,10 +213,57 @@ where
 {
     type Extra = T;
 
-    fn pick(&self, key: &str) -> Option<ServiceData<T>> {
-        self.ring
-            .get_node(key)
-            .and_then(|node_id| self.instances.get(node_id).cloned())
+    fn pick_where(&self, key: &str, eligible: Eligible<T>) -> Option<ServiceData<T>> {
+        let node_id = match self.load_factor {
+            Some(epsilon) => self.ring.get_node_bounded(key, epsilon, |node_id| {
+                self.instances
+                    .get(node_id)
+                    .map_or(0, |data| data.in_flight())
+            }),
+            None => self.ring.get_node(key),
+        };
+
+        match node_id.and_then(|node_id| self.instances.get(node_id)) {
+            Some(data) if eligible(data) => Some(data.clone()),
+            // Route around an excluded instance to the next eligible successor,
+            // so that the keys of a failing instance spread over the ring.
+            _ => self
+                .ring
+                .get_nodes(key, self.instances.len())
+                .into_iter()
+                .filter_map(|node_id| self.instances.get(node_id))
+                .find(|data| eligible(data))
+                .cloned(),
+        }
+    }
+
+    fn candidates_where(
+        &self,
+        key: &str,
+        count: usize,
+        eligible: Eligible<T>,
+    ) -> Vec<ServiceData<T>> {
+        let Some(first) = self.pick_where(key, eligible) else {
+            return Vec::new();
+        };
+
+        let first_id = first.entry().info().id();
+
+        // Fall back to the successors of the key on the ring, so that every
+        // key fails over to the same instances.
+        let successors = self
+            .ring
+            .get_nodes(key, self.instances.len())
+            .into_iter()
+            .filter(|node_id| *node_id != first_id)
+            .filter_map(|node_id| self.instances.get(node_id))
+            .filter(|data| eligible(data))
+            .cloned();
+
+        std::iter::once(first.clone())
+            .chain(successors)
+            .take(count)
+            .collect()
     }
 
     fn list(&self) -> Vec<ServiceData<T>> {
@@ -93,12 +277,22 @@ where
         for data in datas {
             let node_id = data.entry().info().id().to_string();
 
-            new_ring.add_node(&node_id);
             new_instances.insert(node_id, data);
         }
 
+        new_ring.add_nodes(new_instances.keys().map(String::as_str));
+
         self.ring = new_ring;
         self.instances = new_instances;
+
+        let distribution = self.ring.distribution();
+
+        debug!(
+            "Hash ring rebuilt with {} nodes, largest share is {:.2}x the ideal one: {:?}",
+            self.ring.node_count(),
+            distribution.max_over_ideal,
+            distribution.shares
+        );
     }
 
     fn clear(&mut self) {
@@ -106,3 +300,320 @@ where
         self.instances.clear();
     }
 }
+
+/// Load-balancing strategy of an upstream service.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    /// Sticks each routing key to one instance.
+    #[default]
+    ConsistHash,
+    /// Sticks each routing key to the instance with the highest random weight.
+    Rendezvous,
+    /// Sticks each routing key to an instance ordinal with jump hashing.
+    JumpHash,
+    RoundRobin,
+    /// Picks instances at random, proportionally to their weight.
+    WeightedRandom,
+    /// Picks the less loaded of two random instances.
+    PowerOfTwoChoices,
+    /// Picks the instance with the fewest outstanding requests.
+    LeastRequests,
+}
+
+/// `BalancedStore` lets each upstream choose its `Store` implementation from
+/// the config while the registries keep a single concrete type.
+#[derive(Clone, Debug)]
+pub enum BalancedStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    ConsistHash(ConsistHashStore<T>),
+    Rendezvous(RendezvousStore<T>),
+    JumpHash(JumpHashStore<T>),
+    RoundRobin(RoundRobinStore<T>),
+    WeightedRandom(WeightedRandomStore<T>),
+    PowerOfTwoChoices(P2cStore<T>),
+    LeastRequests(LeastRequestsStore<T>),
+}
+
+impl<T> BalancedStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    pub fn new(
+        strategy: BalanceStrategy,
+        replicas: usize,
+        hasher: Hasher,
+        load_factor: Option<f64>,
+    ) -> Self {
+        match strategy {
+            BalanceStrategy::ConsistHash => {
+                let store = ConsistHashStore::new(replicas, hasher);
+
+                match load_factor {
+                    Some(epsilon) => Self::ConsistHash(store.with_bounded_load(epsilon)),
+                    None => Self::ConsistHash(store),
+                }
+            }
+            BalanceStrategy::Rendezvous => Self::Rendezvous(RendezvousStore::new(hasher)),
+            BalanceStrategy::JumpHash => Self::JumpHash(JumpHashStore::new(hasher)),
+            BalanceStrategy::RoundRobin => Self::RoundRobin(RoundRobinStore::new()),
+            BalanceStrategy::WeightedRandom => Self::WeightedRandom(WeightedRandomStore::new()),
+            BalanceStrategy::PowerOfTwoChoices => Self::PowerOfTwoChoices(P2cStore::new()),
+            BalanceStrategy::LeastRequests => Self::LeastRequests(LeastRequestsStore::new()),
+        }
+    }
+
+    fn inner(&self) -> &dyn Store<Extra = T> {
+        match self {
+            Self::ConsistHash(store) => store,
+            Self::Rendezvous(store) => store,
+            Self::JumpHash(store) => store,
+            Self::RoundRobin(store) => store,
+            Self::WeightedRandom(store) => store,
+            Self::PowerOfTwoChoices(store) => store,
+            Self::LeastRequests(store) => store,
+        }
+    }
+
+    fn inner_mut(&mut self) -> &mut dyn Store<Extra = T> {
+        match self {
+            Self::ConsistHash(store) => store,
+            Self::Rendezvous(store) => store,
+            Self::JumpHash(store) => store,
+            Self::RoundRobin(store) => store,
+            Self::WeightedRandom(store) => store,
+            Self::PowerOfTwoChoices(store) => store,
+            Self::LeastRequests(store) => store,
+        }
+    }
+}
+
+impl<T> Store for BalancedStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    type Extra = T;
+
+    fn pick_where(&self, key: &str, eligible: Eligible<T>) -> Option<ServiceData<T>> {
+        self.inner().pick_where(key, eligible)
+    }
+
+    fn candidates_where(
+        &self,
+        key: &str,
+        count: usize,
+        eligible: Eligible<T>,
+    ) -> Vec<ServiceData<T>> {
+        self.inner().candidates_where(key, count, eligible)
+    }
+
+    fn list(&self) -> Vec<ServiceData<T>> {
+        self.inner().list()
+    }
+
+    fn update(&mut self, datas: Vec<ServiceData<T>>) {
+        self.inner_mut().update(datas)
+    }
+
+    fn clear(&mut self) {
+        self.inner_mut().clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{consist_hash::HashAlgorithm, registry::model::ServiceInfo};
+
+    const KEYS: usize = 10_000;
+
+    fn instances(count: usize) -> Vec<ServiceData<()>> {
+        (0..count)
+            .map(|ordinal| {
+                let info = ServiceInfo::new(
+                    format!("instance-{}", ordinal),
+                    "upstream".to_string(),
+                    "127.0.0.1".to_string(),
+                    9000,
+                )
+                .with_meta(HashMap::from([(
+                    "ordinal".to_string(),
+                    ordinal.to_string(),
+                )]));
+
+                ServiceData::new(ServiceEntry::new(info), ())
+            })
+            .collect()
+    }
+
+    fn owners(store: &impl Store<Extra = ()>) -> Vec<String> {
+        (0..KEYS)
+            .map(|key| {
+                let data = store.pick(&format!("key-{}", key)).unwrap();
+
+                data.entry().info().id().to_string()
+            })
+            .collect()
+    }
+
+    fn stores() -> Vec<(&'static str, BalancedStore<()>)> {
+        let hasher = HashAlgorithm::XxHash64.hasher();
+
+        [
+            ("ring", BalanceStrategy::ConsistHash),
+            ("rendezvous", BalanceStrategy::Rendezvous),
+            ("jump", BalanceStrategy::JumpHash),
+        ]
+        .into_iter()
+        .map(|(name, strategy)| (name, BalancedStore::new(strategy, 100, hasher, None)))
+        .collect()
+    }
+
+    /// Moves from `before` to `after`, checking every moved key went to or
+    /// came from `node` and that about `expected` keys moved.
+    fn assert_moved(name: &str, before: &[String], after: &[String], node: &str, expected: usize) {
+        let moved = before
+            .iter()
+            .zip(after)
+            .filter(|(before, after)| before != after)
+            .inspect(|(before, after)| {
+                assert!(
+                    *before == node || *after == node,
+                    "{} moved a key from {} to {}",
+                    name,
+                    before,
+                    after
+                );
+            })
+            .count();
+
+        assert!(
+            moved > expected / 2 && moved < expected * 3 / 2,
+            "{} moved {} keys, expected about {}",
+            name,
+            moved,
+            expected
+        );
+    }
+
+    #[test]
+    fn adding_an_instance_moves_about_one_nth_of_keys() {
+        for (name, mut store) in stores() {
+            store.update(instances(10));
+            let before = owners(&store);
+
+            store.update(instances(11));
+            let after = owners(&store);
+
+            assert_moved(name, &before, &after, "instance-10", KEYS / 11);
+        }
+    }
+
+    #[test]
+    fn removing_the_last_instance_moves_only_its_keys() {
+        for (name, mut store) in stores() {
+            store.update(instances(11));
+            let before = owners(&store);
+
+            store.update(instances(10));
+            let after = owners(&store);
+
+            assert_moved(name, &before, &after, "instance-10", KEYS / 11);
+        }
+    }
+
+    #[test]
+    fn removing_a_middle_instance_moves_only_its_keys() {
+        // Jump hashing renumbers every instance after the removed one, so only
+        // the ring and rendezvous hashing are expected to keep other keys.
+        for (name, mut store) in stores().into_iter().filter(|(name, _)| *name != "jump") {
+            let mut datas = instances(11);
+            store.update(datas.clone());
+            let before = owners(&store);
+
+            datas.remove(3);
+            store.update(datas);
+            let after = owners(&store);
+
+            assert_moved(name, &before, &after, "instance-3", KEYS / 11);
+        }
+    }
+
+    fn open_breaker(data: &ServiceData<()>) {
+        let config = BreakerConfig::default();
+
+        while data.is_available() {
+            data.breaker().record_failure(&config);
+        }
+    }
+
+    #[test]
+    fn every_strategy_skips_open_breakers() {
+        let hasher = HashAlgorithm::XxHash64.hasher();
+
+        for (name, strategy) in [
+            ("ring", BalanceStrategy::ConsistHash),
+            ("rendezvous", BalanceStrategy::Rendezvous),
+            ("jump", BalanceStrategy::JumpHash),
+            ("round_robin", BalanceStrategy::RoundRobin),
+            ("weighted_random", BalanceStrategy::WeightedRandom),
+            ("p2c", BalanceStrategy::PowerOfTwoChoices),
+            ("least_requests", BalanceStrategy::LeastRequests),
+        ] {
+            let mut store = BalancedStore::new(strategy, 100, hasher, None);
+            let datas = instances(5);
+            store.update(datas.clone());
+
+            open_breaker(&datas[1]);
+            open_breaker(&datas[3]);
+
+            for key in 0..1_000 {
+                let key = format!("key-{}", key);
+
+                let picked = store.pick(&key).unwrap();
+                assert!(picked.is_available(), "{} picked an open instance", name);
+
+                let candidates = store.candidates(&key, 5);
+                let mut ids: Vec<_> = candidates
+                    .iter()
+                    .map(|data| data.entry().info().id())
+                    .collect();
+                ids.sort_unstable();
+
+                assert_eq!(
+                    ids,
+                    ["instance-0", "instance-2", "instance-4"],
+                    "{} candidates",
+                    name
+                );
+            }
+
+            for data in &datas {
+                open_breaker(data);
+            }
+
+            assert!(
+                store.pick("key").is_none(),
+                "{} picked an open instance",
+                name
+            );
+            assert!(store.candidates("key", 5).is_empty(), "{} candidates", name);
+        }
+    }
+
+    #[test]
+    fn hashing_strategies_move_only_the_keys_of_open_instances() {
+        for (name, mut store) in stores() {
+            let datas = instances(10);
+            store.update(datas.clone());
+            let before = owners(&store);
+
+            open_breaker(&datas[3]);
+            let after = owners(&store);
+
+            assert_moved(name, &before, &after, "instance-3", KEYS / 10);
+        }
+    }
+}
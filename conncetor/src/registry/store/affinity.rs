@@ -0,0 +1,204 @@
+use std::fmt::Debug;
+
+use crate::{
+    consist_hash::Hasher,
+    registry::store::{Eligible, ServiceData, Store},
+};
+
+/// Rendezvous (highest random weight) hashing.
+///
+/// Every instance is scored against the key and the highest score wins, so
+/// there are no virtual nodes to tune, the keys are spread evenly even across
+/// a handful of instances, and only the keys of a removed instance move.
+#[derive(Clone, Debug)]
+pub struct RendezvousStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    hasher: Hasher,
+    instances: Vec<ServiceData<T>>,
+}
+
+impl<T> RendezvousStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    pub fn new(hasher: Hasher) -> Self {
+        Self {
+            hasher,
+            instances: Vec::new(),
+        }
+    }
+
+    fn score(&self, data: &ServiceData<T>, key: &str) -> u64 {
+        (self.hasher)(&format!("{}-{}", data.entry().info().id(), key))
+    }
+}
+
+impl<T> Store for RendezvousStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    type Extra = T;
+
+    fn pick_where(&self, key: &str, eligible: Eligible<T>) -> Option<ServiceData<T>> {
+        self.instances
+            .iter()
+            .filter(|data| eligible(data))
+            .max_by_key(|data| self.score(data, key))
+            .cloned()
+    }
+
+    fn candidates_where(
+        &self,
+        key: &str,
+        count: usize,
+        eligible: Eligible<T>,
+    ) -> Vec<ServiceData<T>> {
+        // The runner-ups by score are exactly where the key would move if the
+        // winner disappeared.
+        let mut scored: Vec<_> = self
+            .instances
+            .iter()
+            .filter(|data| eligible(data))
+            .map(|data| (self.score(data, key), data))
+            .collect();
+
+        scored.sort_unstable_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+        scored
+            .into_iter()
+            .take(count)
+            .map(|(_, data)| data.clone())
+            .collect()
+    }
+
+    fn list(&self) -> Vec<ServiceData<T>> {
+        self.instances.clone()
+    }
+
+    fn update(&mut self, datas: Vec<ServiceData<T>>) {
+        self.instances = datas;
+    }
+
+    fn clear(&mut self) {
+        self.instances.clear();
+    }
+}
+
+/// Jump consistent hashing over instance ordinals.
+///
+/// Instances are ordered by their `ordinal` metadata (falling back to their
+/// ID), and a key maps to a position in that order. It needs no memory besides
+/// the list, but only behaves consistently when instances are added or removed
+/// at the end of the ordinal range, e.g. a StatefulSet scaling up or down.
+#[derive(Clone, Debug)]
+pub struct JumpHashStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    hasher: Hasher,
+    instances: Vec<ServiceData<T>>,
+}
+
+impl<T> JumpHashStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    const ORDINAL_META_KEY: &str = "ordinal";
+
+    pub fn new(hasher: Hasher) -> Self {
+        Self {
+            hasher,
+            instances: Vec::new(),
+        }
+    }
+
+    fn bucket(&self, key: &str) -> Option<usize> {
+        match self.instances.len() {
+            0 => None,
+            len => Some(jump_hash((self.hasher)(key), len)),
+        }
+    }
+}
+
+/// The jump consistent hash of Lamping and Veach, mapping `key` to a bucket
+/// in `0..buckets`.
+fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let mut bucket: i64 = -1;
+    let mut next: i64 = 0;
+
+    while next < buckets as i64 {
+        bucket = next;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+
+    bucket as usize
+}
+
+impl<T> Store for JumpHashStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    type Extra = T;
+
+    fn pick_where(&self, key: &str, eligible: Eligible<T>) -> Option<ServiceData<T>> {
+        self.candidates_where(key, 1, eligible).pop()
+    }
+
+    fn candidates_where(
+        &self,
+        key: &str,
+        count: usize,
+        eligible: Eligible<T>,
+    ) -> Vec<ServiceData<T>> {
+        let Some(bucket) = self.bucket(key) else {
+            return Vec::new();
+        };
+
+        let len = self.instances.len();
+
+        // Excluded instances hand their keys to the next ordinals.
+        (0..len)
+            .map(|offset| &self.instances[(bucket + offset) % len])
+            .filter(|data| eligible(data))
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    fn list(&self) -> Vec<ServiceData<T>> {
+        self.instances.clone()
+    }
+
+    fn update(&mut self, mut datas: Vec<ServiceData<T>>) {
+        datas.sort_by(|a, b| {
+            let ordinal = |data: &ServiceData<T>| {
+                data.entry()
+                    .info()
+                    .meta()
+                    .get(Self::ORDINAL_META_KEY)
+                    .and_then(|ordinal| ordinal.parse::<u64>().ok())
+            };
+
+            // Instances without an ordinal sort after the numbered ones.
+            let key = |data: &ServiceData<T>| (ordinal(data).is_none(), ordinal(data));
+
+            key(a).cmp(&key(b)).then_with(|| data_id(a).cmp(data_id(b)))
+        });
+
+        self.instances = datas;
+    }
+
+    fn clear(&mut self) {
+        self.instances.clear();
+    }
+}
+
+fn data_id<T>(data: &ServiceData<T>) -> &str
+where
+    T: Clone + Debug,
+{
+    data.entry().info().id()
+}
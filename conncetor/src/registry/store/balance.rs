@@ -0,0 +1,521 @@
+use std::{
+    fmt::Debug,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crate::registry::store::{Eligible, ServiceData, Store};
+
+/// Cycles through the instances regardless of the routing key.
+#[derive(Clone, Debug)]
+pub struct RoundRobinStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    /// Shared by every snapshot of the store, so a refresh does not restart
+    /// the rotation.
+    next: Arc<AtomicUsize>,
+    instances: Vec<ServiceData<T>>,
+}
+
+impl<T> RoundRobinStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    pub fn new() -> Self {
+        Self {
+            next: Arc::new(AtomicUsize::new(0)),
+            instances: Vec::new(),
+        }
+    }
+}
+
+impl<T> Default for RoundRobinStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Store for RoundRobinStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    type Extra = T;
+
+    fn pick_where(&self, _key: &str, eligible: Eligible<T>) -> Option<ServiceData<T>> {
+        let len = self.instances.len();
+
+        if len == 0 {
+            return None;
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % len;
+
+        // Excluded instances pass their turn to the next one.
+        (0..len)
+            .map(|offset| &self.instances[(start + offset) % len])
+            .find(|data| eligible(data))
+            .cloned()
+    }
+
+    fn list(&self) -> Vec<ServiceData<T>> {
+        self.instances.clone()
+    }
+
+    fn update(&mut self, datas: Vec<ServiceData<T>>) {
+        self.instances = datas;
+    }
+
+    fn clear(&mut self) {
+        self.instances.clear();
+    }
+}
+
+/// Picks a random instance with a probability proportional to its weight.
+#[derive(Clone, Debug)]
+pub struct WeightedRandomStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    /// Running sum of the weights, aligned with `instances`.
+    cumulative: Vec<u64>,
+    instances: Vec<ServiceData<T>>,
+}
+
+impl<T> WeightedRandomStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    pub fn new() -> Self {
+        Self {
+            cumulative: Vec::new(),
+            instances: Vec::new(),
+        }
+    }
+}
+
+impl<T> Default for WeightedRandomStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Store for WeightedRandomStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    type Extra = T;
+
+    fn pick_where(&self, _key: &str, eligible: Eligible<T>) -> Option<ServiceData<T>> {
+        let total = *self.cumulative.last()?;
+
+        let point = rand::random_range(0..total);
+
+        // The first instance whose running sum exceeds the point owns it.
+        let index = self.cumulative.partition_point(|&sum| sum <= point);
+
+        match self.instances.get(index) {
+            Some(data) if eligible(data) => Some(data.clone()),
+            // Draw again among the eligible instances only, which is slower but
+            // keeps their weights relative to each other.
+            _ => {
+                let eligible: Vec<_> = self
+                    .instances
+                    .iter()
+                    .filter(|data| eligible(data))
+                    .collect();
+
+                let total: u64 = eligible.iter().map(|data| weight(data)).sum();
+
+                if total == 0 {
+                    return None;
+                }
+
+                let mut point = rand::random_range(0..total);
+
+                eligible
+                    .into_iter()
+                    .find(|data| match point.checked_sub(weight(data)) {
+                        Some(rest) => {
+                            point = rest;
+                            false
+                        }
+                        None => true,
+                    })
+                    .cloned()
+            }
+        }
+    }
+
+    fn list(&self) -> Vec<ServiceData<T>> {
+        self.instances.clone()
+    }
+
+    fn update(&mut self, datas: Vec<ServiceData<T>>) {
+        let mut sum = 0;
+
+        self.cumulative = datas
+            .iter()
+            .map(|data| {
+                sum += weight(data);
+                sum
+            })
+            .collect();
+        self.instances = datas;
+    }
+
+    fn clear(&mut self) {
+        self.cumulative.clear();
+        self.instances.clear();
+    }
+}
+
+/// Samples two random instances and picks the one with fewer outstanding
+/// requests, which avoids the herding of a strict least-requests choice.
+#[derive(Clone, Debug)]
+pub struct P2cStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    instances: Vec<ServiceData<T>>,
+}
+
+impl<T> P2cStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    pub fn new() -> Self {
+        Self {
+            instances: Vec::new(),
+        }
+    }
+}
+
+impl<T> Default for P2cStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Store for P2cStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    type Extra = T;
+
+    fn pick_where(&self, _key: &str, eligible: Eligible<T>) -> Option<ServiceData<T>> {
+        if self.instances.iter().all(eligible) {
+            return p2c(&self.instances).cloned();
+        }
+
+        let eligible: Vec<_> = self
+            .instances
+            .iter()
+            .filter(|data| eligible(data))
+            .cloned()
+            .collect();
+
+        p2c(&eligible).cloned()
+    }
+
+    fn list(&self) -> Vec<ServiceData<T>> {
+        self.instances.clone()
+    }
+
+    fn update(&mut self, datas: Vec<ServiceData<T>>) {
+        self.instances = datas;
+    }
+
+    fn clear(&mut self) {
+        self.instances.clear();
+    }
+}
+
+/// Picks the instance with the fewest outstanding requests.
+#[derive(Clone, Debug)]
+pub struct LeastRequestsStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    instances: Vec<ServiceData<T>>,
+}
+
+impl<T> LeastRequestsStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    pub fn new() -> Self {
+        Self {
+            instances: Vec::new(),
+        }
+    }
+}
+
+impl<T> Default for LeastRequestsStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Store for LeastRequestsStore<T>
+where
+    T: Clone + Debug + Send + Sync,
+{
+    type Extra = T;
+
+    fn pick_where(&self, _key: &str, eligible: Eligible<T>) -> Option<ServiceData<T>> {
+        let len = self.instances.len();
+
+        if len == 0 {
+            return None;
+        }
+
+        // Start the scan at a random offset so that ties are spread out.
+        let offset = rand::random_range(0..len);
+
+        (0..len)
+            .map(|i| &self.instances[(offset + i) % len])
+            .filter(|data| eligible(data))
+            .min_by_key(|data| data.in_flight())
+            .cloned()
+    }
+
+    fn list(&self) -> Vec<ServiceData<T>> {
+        self.instances.clone()
+    }
+
+    fn update(&mut self, datas: Vec<ServiceData<T>>) {
+        self.instances = datas;
+    }
+
+    fn clear(&mut self) {
+        self.instances.clear();
+    }
+}
+
+fn weight<T>(data: &ServiceData<T>) -> u64
+where
+    T: Clone + Debug,
+{
+    data.entry().info().weight() as u64
+}
+
+/// The less loaded of two distinct random instances.
+fn p2c<T>(instances: &[ServiceData<T>]) -> Option<&ServiceData<T>>
+where
+    T: Clone + Debug,
+{
+    let len = instances.len();
+
+    if len < 2 {
+        return instances.first();
+    }
+
+    let first = rand::random_range(0..len);
+    // Offset the second sample so that both are always distinct.
+    let second = (first + rand::random_range(1..len)) % len;
+
+    let (first, second) = (&instances[first], &instances[second]);
+
+    if second.in_flight() < first.in_flight() {
+        Some(second)
+    } else {
+        Some(first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::registry::model::{ServiceEntry, ServiceInfo};
+
+    const PICKS: usize = 10_000;
+
+    /// One instance per weight, named `instance-<index>`.
+    fn instances(weights: &[u32]) -> Vec<ServiceData<()>> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(index, weight)| {
+                let info = ServiceInfo::new(
+                    format!("instance-{}", index),
+                    "upstream".to_string(),
+                    "127.0.0.1".to_string(),
+                    9000,
+                )
+                .with_weight(*weight);
+
+                ServiceData::new(ServiceEntry::new(info), ())
+            })
+            .collect()
+    }
+
+    fn id(data: &ServiceData<()>) -> &str {
+        data.entry().info().id()
+    }
+
+    fn counts(store: &impl Store<Extra = ()>) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+
+        for _ in 0..PICKS {
+            *counts
+                .entry(id(&store.pick("key").unwrap()).to_string())
+                .or_default() += 1;
+        }
+
+        counts
+    }
+
+    #[test]
+    fn empty_stores_pick_nothing() {
+        assert!(RoundRobinStore::<()>::new().pick("key").is_none());
+        assert!(WeightedRandomStore::<()>::new().pick("key").is_none());
+        assert!(P2cStore::<()>::new().pick("key").is_none());
+        assert!(LeastRequestsStore::<()>::new().pick("key").is_none());
+    }
+
+    #[test]
+    fn round_robin_rotates_through_instances() {
+        let mut store = RoundRobinStore::new();
+        store.update(instances(&[1, 1, 1]));
+
+        let picked: Vec<_> = (0..6)
+            .map(|_| id(&store.pick("key").unwrap()).to_string())
+            .collect();
+
+        assert_eq!(
+            picked,
+            [
+                "instance-0",
+                "instance-1",
+                "instance-2",
+                "instance-0",
+                "instance-1",
+                "instance-2"
+            ]
+        );
+    }
+
+    #[test]
+    fn round_robin_snapshots_share_the_rotation() {
+        let mut store = RoundRobinStore::new();
+        store.update(instances(&[1, 1, 1]));
+
+        assert_eq!(id(&store.pick("key").unwrap()), "instance-0");
+
+        let mut snapshot = store.clone();
+        snapshot.update(instances(&[1, 1, 1]));
+
+        assert_eq!(id(&snapshot.pick("key").unwrap()), "instance-1");
+        assert_eq!(id(&store.pick("key").unwrap()), "instance-2");
+    }
+
+    #[test]
+    fn weighted_random_keeps_running_sums() {
+        let mut store = WeightedRandomStore::new();
+        store.update(instances(&[1, 3, 0, 6]));
+
+        // A zero weight counts as one so that the instance stays reachable.
+        assert_eq!(store.cumulative, [1, 4, 5, 11]);
+
+        store.clear();
+
+        assert!(store.cumulative.is_empty());
+        assert!(store.pick("key").is_none());
+    }
+
+    #[test]
+    fn weighted_random_follows_the_weights() {
+        let mut store = WeightedRandomStore::new();
+        store.update(instances(&[1, 3, 6]));
+
+        let counts = counts(&store);
+
+        for (instance, share) in [
+            ("instance-0", 0.1),
+            ("instance-1", 0.3),
+            ("instance-2", 0.6),
+        ] {
+            let picked = counts.get(instance).copied().unwrap_or_default() as f64 / PICKS as f64;
+
+            assert!(
+                (picked - share).abs() < 0.03,
+                "{} got {:.3} of the picks, expected {}",
+                instance,
+                picked,
+                share
+            );
+        }
+    }
+
+    #[test]
+    fn p2c_avoids_the_busier_instance() {
+        let mut store = P2cStore::new();
+        let datas = instances(&[1, 1, 1]);
+        store.update(datas.clone());
+
+        let _busy = datas[1].track();
+
+        let counts = counts(&store);
+
+        assert!(!counts.contains_key("instance-1"));
+        assert!(counts.contains_key("instance-0"));
+        assert!(counts.contains_key("instance-2"));
+    }
+
+    #[test]
+    fn p2c_picks_the_only_instance() {
+        let mut store = P2cStore::new();
+        let datas = instances(&[1]);
+        store.update(datas.clone());
+
+        let _busy = datas[0].track();
+
+        assert_eq!(id(&store.pick("key").unwrap()), "instance-0");
+    }
+
+    #[test]
+    fn least_requests_picks_the_idlest_instance() {
+        let mut store = LeastRequestsStore::new();
+        let datas = instances(&[1, 1, 1]);
+        store.update(datas.clone());
+
+        let _first = [datas[0].track(), datas[0].track()];
+        let second = datas[1].track();
+        let _third = [datas[2].track(), datas[2].track()];
+
+        assert_eq!(id(&store.pick("key").unwrap()), "instance-1");
+
+        // Finishing the request makes it the only idle instance.
+        drop(second);
+        assert_eq!(datas[1].in_flight(), 0);
+        assert_eq!(id(&store.pick("key").unwrap()), "instance-1");
+    }
+
+    #[test]
+    fn least_requests_spreads_ties() {
+        let mut store = LeastRequestsStore::new();
+        store.update(instances(&[1, 1, 1]));
+
+        let counts = counts(&store);
+
+        assert_eq!(counts.len(), 3);
+        assert!(counts.values().all(|count| *count > PICKS / 6));
+    }
+}
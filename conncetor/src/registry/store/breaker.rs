@@ -0,0 +1,287 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Thresholds of the circuit breakers of an upstream's instances.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BreakerConfig {
+    /// Consecutive failures that open the breaker.
+    #[serde(default = "BreakerConfig::default_failure_threshold")]
+    failure_threshold: u32,
+    /// How long an open breaker rejects calls before letting probes through.
+    #[serde(default = "BreakerConfig::default_open_secs")]
+    open_secs: u64,
+    /// Concurrent probes allowed while half-open.
+    #[serde(default = "BreakerConfig::default_half_open_probes")]
+    half_open_probes: u32,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: Self::default_failure_threshold(),
+            open_secs: Self::default_open_secs(),
+            half_open_probes: Self::default_half_open_probes(),
+        }
+    }
+}
+
+impl BreakerConfig {
+    fn default_failure_threshold() -> u32 {
+        5
+    }
+
+    fn default_open_secs() -> u64 {
+        30
+    }
+
+    fn default_half_open_probes() -> u32 {
+        1
+    }
+
+    fn open_delay(&self) -> Duration {
+        Duration::from_secs(self.open_secs)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl BreakerState {
+    /// Value of the state in metrics.
+    pub fn as_gauge(&self) -> u8 {
+        match self {
+            Self::Closed => 0,
+            Self::HalfOpen => 1,
+            Self::Open => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Inner {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probes: u32, since: Instant },
+}
+
+/// Circuit breaker of one upstream instance.
+///
+/// Closed, it counts consecutive failures and opens at the threshold. Open, it
+/// rejects calls until its delay expires. Half-open, it lets a few probes
+/// through: a success closes it again and a failure opens it again.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    inner: Mutex<Inner>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(Inner::Closed { failures: 0 }),
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn state(&self) -> BreakerState {
+        match *self.inner.lock().unwrap() {
+            Inner::Closed { .. } => BreakerState::Closed,
+            Inner::Open { until } if until > Instant::now() => BreakerState::Open,
+            Inner::Open { .. } | Inner::HalfOpen { .. } => BreakerState::HalfOpen,
+        }
+    }
+
+    /// Whether picks may route to the instance, without reserving a probe.
+    pub fn is_available(&self) -> bool {
+        self.state() != BreakerState::Open
+    }
+
+    /// Reserves the right to send a call, which must be followed by
+    /// `record_success` or `record_failure`.
+    pub fn try_acquire(&self, config: &BreakerConfig) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+
+        match *inner {
+            Inner::Closed { .. } => true,
+            Inner::Open { until } if until > Instant::now() => false,
+            Inner::Open { .. } => {
+                *inner = Inner::HalfOpen {
+                    probes: 1,
+                    since: Instant::now(),
+                };
+
+                true
+            }
+            Inner::HalfOpen { ref mut probes, .. } if *probes < config.half_open_probes => {
+                *probes += 1;
+
+                true
+            }
+            // Probes whose call was cancelled never report back, so start over
+            // rather than staying half-open forever.
+            Inner::HalfOpen { since, .. } if since.elapsed() > config.open_delay() => {
+                *inner = Inner::HalfOpen {
+                    probes: 1,
+                    since: Instant::now(),
+                };
+
+                true
+            }
+            Inner::HalfOpen { .. } => false,
+        }
+    }
+
+    pub fn record_success(&self) {
+        *self.inner.lock().unwrap() = Inner::Closed { failures: 0 };
+    }
+
+    /// Returns whether the failure opened the breaker.
+    pub fn record_failure(&self, config: &BreakerConfig) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+
+        let open = match *inner {
+            Inner::Closed { ref mut failures } => {
+                *failures += 1;
+
+                *failures >= config.failure_threshold
+            }
+            Inner::HalfOpen { .. } => true,
+            // A call started before the breaker opened.
+            Inner::Open { .. } => false,
+        };
+
+        if open {
+            *inner = Inner::Open {
+                until: Instant::now() + config.open_delay(),
+            };
+        }
+
+        open
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BreakerConfig {
+        BreakerConfig {
+            failure_threshold: 3,
+            open_secs: 30,
+            half_open_probes: 1,
+        }
+    }
+
+    /// Ends the open delay without waiting for it.
+    fn expire(breaker: &CircuitBreaker) {
+        *breaker.inner.lock().unwrap() = Inner::Open {
+            until: Instant::now(),
+        };
+    }
+
+    fn open(breaker: &CircuitBreaker, config: &BreakerConfig) {
+        for _ in 0..config.failure_threshold {
+            breaker.record_failure(config);
+        }
+
+        assert_eq!(breaker.state(), BreakerState::Open);
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let config = config();
+        let breaker = CircuitBreaker::default();
+
+        assert!(!breaker.record_failure(&config));
+        assert!(!breaker.record_failure(&config));
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(breaker.try_acquire(&config));
+
+        assert!(breaker.record_failure(&config));
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.is_available());
+        assert!(!breaker.try_acquire(&config));
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let config = config();
+        let breaker = CircuitBreaker::default();
+
+        breaker.record_failure(&config);
+        breaker.record_failure(&config);
+        breaker.record_success();
+
+        assert!(!breaker.record_failure(&config));
+        assert!(!breaker.record_failure(&config));
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn lets_limited_probes_through_once_the_delay_expires() {
+        let config = config();
+        let breaker = CircuitBreaker::default();
+
+        open(&breaker, &config);
+        expire(&breaker);
+
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(breaker.is_available());
+
+        assert!(breaker.try_acquire(&config));
+        assert!(!breaker.try_acquire(&config));
+    }
+
+    #[test]
+    fn probe_success_closes_the_breaker() {
+        let config = config();
+        let breaker = CircuitBreaker::default();
+
+        open(&breaker, &config);
+        expire(&breaker);
+
+        assert!(breaker.try_acquire(&config));
+        breaker.record_success();
+
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(breaker.try_acquire(&config));
+    }
+
+    #[test]
+    fn probe_failure_opens_the_breaker_again() {
+        let config = config();
+        let breaker = CircuitBreaker::default();
+
+        open(&breaker, &config);
+        expire(&breaker);
+
+        assert!(breaker.try_acquire(&config));
+        assert!(breaker.record_failure(&config));
+
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.try_acquire(&config));
+    }
+
+    #[test]
+    fn lost_probes_do_not_keep_it_half_open() {
+        let config = config();
+        let breaker = CircuitBreaker::default();
+
+        *breaker.inner.lock().unwrap() = Inner::HalfOpen {
+            probes: 1,
+            since: Instant::now() - config.open_delay() - Duration::from_secs(1),
+        };
+
+        assert!(breaker.try_acquire(&config));
+        assert!(!breaker.try_acquire(&config));
+    }
+}
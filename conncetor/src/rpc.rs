@@ -1,54 +1,95 @@
 use std::{sync::Arc, time::Duration};
 
 use anyhow::anyhow;
-use tokio::sync::Notify;
+use tokio::{net::TcpListener, sync::Notify};
 use tonic::transport::Channel;
-use tracing::debug;
+use tracing::{debug, warn};
 
+mod auth;
 mod dispatch;
+mod health;
 
 use crate::{
+    config::AppConfig,
     registry::{
-        ConsulRegistry,
+        ServiceRegistry,
+        discovery::Discovery,
         model::{HeathCheck, Registry, ServiceEntry},
-        store::ConsistHashStore,
+        snapshot::RegistrySnapshot,
+        store::BalancedStore,
     },
-    rpc::dispatch::{DispatchServer, dispatcher::dispatch_service_server::DispatchServiceServer},
+    rpc::{
+        auth::DispatchAuth,
+        dispatch::{
+            DispatchServer,
+            dispatcher::{self, dispatch_service_server::DispatchServiceServer},
+        },
+        health::spawn_health_reporter,
+    },
+    service::RetryBudget,
     state::AppState,
+    tls::{ClientTls, ServerTls},
 };
 
-async fn transformer(entry: ServiceEntry) -> Channel {
+async fn transformer(
+    entry: ServiceEntry,
+    lazy_connect: bool,
+    connect_timeout: Duration,
+    tls: Option<ClientTls>,
+) -> anyhow::Result<Channel> {
     let addr = format!("http://{}", entry.info().address());
 
-    Channel::from_shared(addr).unwrap().connect().await.unwrap()
+    let endpoint = Channel::from_shared(addr.clone())
+        .map_err(|err| anyhow!("Invalid upstream address {}: {}", addr, err))?
+        .connect_timeout(connect_timeout);
+
+    // A lazy channel connects on its first request and reconnects on its own,
+    // so an instance being down right now does not keep it out of the store.
+    match (tls, lazy_connect) {
+        (Some(tls), true) => Ok(endpoint.connect_with_connector_lazy(tls.connector())),
+        (Some(tls), false) => endpoint
+            .connect_with_connector(tls.connector())
+            .await
+            .map_err(|err| anyhow!("Error when connecting to upstream {}: {}", addr, err)),
+        (None, true) => Ok(endpoint.connect_lazy()),
+        (None, false) => endpoint
+            .connect()
+            .await
+            .map_err(|err| anyhow!("Error when connecting to upstream {}: {}", addr, err)),
+    }
 }
 
-const REPLICAS: usize = 5;
-
-const DEFAULT_HASHER: fn(&str) -> u64 = |key: &str| {
-    use std::hash::Hasher as StdHasher;
-
-    let mut default_hasher = twox_hash::XxHash64::with_seed(0);
-
-    default_hasher.write(key.as_bytes());
-
-    default_hasher.finish()
-};
-
 const USER_SERVICE_PREFIX: &str = "UserService";
 
-pub async fn init_user_service(consul_addr: &str) -> anyhow::Result<ConsulRegistry<Channel>> {
-    let store = ConsistHashStore::new(REPLICAS, DEFAULT_HASHER);
+pub async fn init_user_service(
+    discovery: Arc<dyn Discovery>,
+    config: &AppConfig,
+    retry_budget: Arc<RetryBudget>,
+    tls: Option<ClientTls>,
+) -> anyhow::Result<ServiceRegistry<Channel>> {
+    let lazy_connect = config.lazy_connect();
+    let connect_timeout = config.connect_timeout();
+    let transformer = move |entry| transformer(entry, lazy_connect, connect_timeout, tls.clone());
+
+    let upstream = config.upstream(USER_SERVICE_PREFIX);
+
+    let store = BalancedStore::new(
+        upstream.strategy(),
+        upstream.replicas(),
+        upstream.hasher().hasher(),
+        upstream.load_factor(),
+    );
 
-    let registry = ConsulRegistry::new(consul_addr, USER_SERVICE_PREFIX, store).map_err(|err| {
-        anyhow!(
-            "Error when initiating user service registry client: {}",
-            err
-        )
-    })?;
+    let mut registry = ServiceRegistry::new(discovery, USER_SERVICE_PREFIX, store)
+        .with_upstream(upstream)
+        .with_retry_budget(retry_budget);
+
+    if let Some(dir) = config.registry_snapshot_dir() {
+        registry = registry.with_snapshot(RegistrySnapshot::new(dir, USER_SERVICE_PREFIX));
+    }
 
     registry
-        .update_store(transformer)
+        .update_store(&transformer)
         .await
         .map_err(|err| anyhow!("Error when updating user service registry store: {}", err))?;
 
@@ -64,18 +105,34 @@ pub async fn init_user_service(consul_addr: &str) -> anyhow::Result<ConsulRegist
 
 const CHANNEL_SERVICE_PREFIX: &str = "ChannelService";
 
-pub async fn init_channel_service(consul_addr: &str) -> anyhow::Result<ConsulRegistry<Channel>> {
-    let store = ConsistHashStore::new(REPLICAS, DEFAULT_HASHER);
+pub async fn init_channel_service(
+    discovery: Arc<dyn Discovery>,
+    config: &AppConfig,
+    retry_budget: Arc<RetryBudget>,
+    tls: Option<ClientTls>,
+) -> anyhow::Result<ServiceRegistry<Channel>> {
+    let lazy_connect = config.lazy_connect();
+    let connect_timeout = config.connect_timeout();
+    let transformer = move |entry| transformer(entry, lazy_connect, connect_timeout, tls.clone());
+
+    let upstream = config.upstream(CHANNEL_SERVICE_PREFIX);
+
+    let store = BalancedStore::new(
+        upstream.strategy(),
+        upstream.replicas(),
+        upstream.hasher().hasher(),
+        upstream.load_factor(),
+    );
 
-    let registry =
-        ConsulRegistry::new(consul_addr, CHANNEL_SERVICE_PREFIX, store).map_err(|err| {
-            anyhow!(
-                "Error when initiating channel service registry client: {}",
-                err
-            )
-        })?;
+    let mut registry = ServiceRegistry::new(discovery, CHANNEL_SERVICE_PREFIX, store)
+        .with_upstream(upstream)
+        .with_retry_budget(retry_budget);
 
-    registry.update_store(transformer).await.map_err(|err| {
+    if let Some(dir) = config.registry_snapshot_dir() {
+        registry = registry.with_snapshot(RegistrySnapshot::new(dir, CHANNEL_SERVICE_PREFIX));
+    }
+
+    registry.update_store(&transformer).await.map_err(|err| {
         anyhow!(
             "Error when updating channel service registry store: {}",
             err
@@ -94,18 +151,34 @@ pub async fn init_channel_service(consul_addr: &str) -> anyhow::Result<ConsulReg
 
 const MESSAGE_SERVICE_PREFIX: &str = "MessageService";
 
-pub async fn init_message_service(consul_addr: &str) -> anyhow::Result<ConsulRegistry<Channel>> {
-    let store = ConsistHashStore::new(REPLICAS, DEFAULT_HASHER);
+pub async fn init_message_service(
+    discovery: Arc<dyn Discovery>,
+    config: &AppConfig,
+    retry_budget: Arc<RetryBudget>,
+    tls: Option<ClientTls>,
+) -> anyhow::Result<ServiceRegistry<Channel>> {
+    let lazy_connect = config.lazy_connect();
+    let connect_timeout = config.connect_timeout();
+    let transformer = move |entry| transformer(entry, lazy_connect, connect_timeout, tls.clone());
+
+    let upstream = config.upstream(MESSAGE_SERVICE_PREFIX);
+
+    let store = BalancedStore::new(
+        upstream.strategy(),
+        upstream.replicas(),
+        upstream.hasher().hasher(),
+        upstream.load_factor(),
+    );
 
-    let registry =
-        ConsulRegistry::new(consul_addr, MESSAGE_SERVICE_PREFIX, store).map_err(|err| {
-            anyhow!(
-                "Error when initiating message service registry client: {}",
-                err
-            )
-        })?;
+    let mut registry = ServiceRegistry::new(discovery, MESSAGE_SERVICE_PREFIX, store)
+        .with_upstream(upstream)
+        .with_retry_budget(retry_budget);
 
-    registry.update_store(transformer).await.map_err(|err| {
+    if let Some(dir) = config.registry_snapshot_dir() {
+        registry = registry.with_snapshot(RegistrySnapshot::new(dir, MESSAGE_SERVICE_PREFIX));
+    }
+
+    registry.update_store(&transformer).await.map_err(|err| {
         anyhow!(
             "Error when updating message service registry store: {}",
             err
@@ -122,30 +195,52 @@ pub async fn init_message_service(consul_addr: &str) -> anyhow::Result<ConsulReg
     Ok(registry)
 }
 
-pub async fn run_dispatch_server(state: &AppState, shutdown: Arc<Notify>) -> anyhow::Result<()> {
-    let dispatch_addr = format!(
-        "{}:{}",
-        state.config().grpc_host(),
-        state.config().grpc_port()
+pub async fn init_connector_registry(
+    discovery: Arc<dyn Discovery>,
+    config: &AppConfig,
+) -> anyhow::Result<ServiceRegistry<()>> {
+    let service_name = config.websock_service_name();
+
+    let transformer = |_| async { anyhow::Ok(()) };
+
+    let upstream = config.upstream(&service_name);
+
+    let store = BalancedStore::new(
+        upstream.strategy(),
+        upstream.replicas(),
+        upstream.hasher().hasher(),
+        upstream.load_factor(),
     );
 
-    let registry = ConsulRegistry::new(
-        &format!(
-            "http://{}:{}",
-            state.config().consul_host(),
-            state.config().consul_port()
-        ),
-        state.config().service_name(),
-        ConsistHashStore::<()>::new(REPLICAS, DEFAULT_HASHER),
-    )
-    .map_err(|err| {
+    let registry = ServiceRegistry::new(discovery, &service_name, store).with_upstream(upstream);
+
+    // Connectors register their WebSocket endpoint once they are up, so on a
+    // cold start there may be none yet; the refresh task picks them up later.
+    if let Err(err) = registry.update_store(transformer).await {
+        warn!(
+            "Starting with an empty connector registry, first refresh failed: {}",
+            err
+        );
+    }
+
+    registry.spawn_update_store(transformer).map_err(|err| {
         anyhow!(
-            "Error when initiating dispatch service registry client: {}",
+            "Error when spawning update store task for connector registry: {}",
             err
         )
     })?;
 
-    let ttl = Duration::from_secs(state.config().refresh_ttl_secs() as u64);
+    Ok(registry)
+}
+
+pub async fn run_dispatch_server(state: &AppState, shutdown: Arc<Notify>) -> anyhow::Result<()> {
+    let dispatch_addr = format!(
+        "{}:{}",
+        state.config().grpc_host(),
+        state.config().grpc_port()
+    );
+
+    let ttl = Duration::from_secs(state.config().refresh_ttl_secs());
 
     let check_id = format!(
         "{}-{}",
@@ -153,35 +248,108 @@ pub async fn run_dispatch_server(state: &AppState, shutdown: Arc<Notify>) -> any
         state.config().service_id()
     );
 
+    // Consul either probes the health service itself or waits for the TTL to
+    // be refreshed.
+    let check = match state.config().grpc_health_check() {
+        true => HeathCheck::grpc(
+            dispatch_addr.clone(),
+            state.config().grpc_check_interval(),
+            check_id,
+            state.config().service_name().to_string(),
+        )
+        .with_tls(state.config().grpc_tls().server.is_some()),
+        false => HeathCheck::new(ttl, check_id, state.config().service_name().to_string()),
+    };
+
     // A background task to refresh service registry is spwawned automatically.
-    registry
+    state
+        .discovery()
         .register(
             Registry::new(
                 state.config().service_id().to_string(),
                 state.config().service_name().to_string(),
                 state.config().grpc_host().to_string(),
                 state.config().grpc_port(),
-                HeathCheck::new(
-                    ttl.clone(),
-                    check_id,
-                    state.config().service_name().to_string(),
-                ),
-            ),
-            ttl.clone(),
+                check,
+            )
+            .with_tags(state.config().service_tags().to_vec()),
+            ttl,
         )
         .await?;
 
-    let dispatch_server = DispatchServer::new(&state);
+    let dispatch_server = DispatchServer::new(state);
 
     let shutdown_signal = shutdown.clone();
-
-    tonic::transport::Server::builder()
-        .add_service(DispatchServiceServer::new(dispatch_server))
-        .serve_with_shutdown(dispatch_addr.parse()?, async move {
-            debug!("gRPC server awaiting shutdown signal");
-            shutdown_signal.notified().await;
-            debug!("gRPC server received shutdown signal");
-        })
-        .await
-        .map_err(|err| anyhow!("Error running dispatch gRPC server: {}", err))
+    let shutdown_signal = async move {
+        debug!("gRPC server awaiting shutdown signal");
+        shutdown_signal.notified().await;
+        debug!("gRPC server received shutdown signal");
+    };
+
+    let grpc_tls = state.config().grpc_tls();
+
+    let client_certs_required = grpc_tls
+        .server
+        .as_ref()
+        .is_some_and(|server_tls| server_tls.require_client_cert);
+
+    if state.config().dispatch_auth().is_none() && !client_certs_required {
+        warn!("DispatchService accepts unauthenticated calls, set dispatch_auth to restrict it");
+    }
+
+    if state.config().dispatch_auth().is_some() && grpc_tls.server.is_none() {
+        warn!("DispatchService runs without TLS, its credentials travel in plaintext");
+    }
+
+    let auth = DispatchAuth::new(state.config().dispatch_auth());
+
+    let (health_reporter, health_server) = tonic_health::server::health_reporter();
+
+    spawn_health_reporter(state.clone(), health_reporter);
+
+    let reflection_v1 = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(dispatcher::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build_v1()
+        .map_err(|err| anyhow!("Error building gRPC reflection service: {}", err))?;
+
+    // Older clients such as some grpcurl releases only speak v1alpha.
+    let reflection_v1alpha = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(dispatcher::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build_v1alpha()
+        .map_err(|err| anyhow!("Error building gRPC reflection service: {}", err))?;
+
+    // Health checking and reflection stay reachable without dispatch
+    // credentials, for Consul and operators.
+    let router = tonic::transport::Server::builder()
+        .add_service(DispatchServiceServer::with_interceptor(
+            dispatch_server,
+            auth,
+        ))
+        .add_service(health_server)
+        .add_service(reflection_v1)
+        .add_service(reflection_v1alpha);
+
+    let result = match &grpc_tls.server {
+        Some(server_tls) => {
+            let tls = ServerTls::new(server_tls, grpc_tls.reload_interval())
+                .map_err(|err| anyhow!("Error when loading gRPC server TLS: {}", err))?;
+
+            let listener = TcpListener::bind(&dispatch_addr).await.map_err(|err| {
+                anyhow!("Error binding gRPC server to {}: {}", dispatch_addr, err)
+            })?;
+
+            router
+                .serve_with_incoming_shutdown(tls.incoming(listener), shutdown_signal)
+                .await
+        }
+        None => {
+            router
+                .serve_with_shutdown(dispatch_addr.parse()?, shutdown_signal)
+                .await
+        }
+    };
+
+    result.map_err(|err| anyhow!("Error running dispatch gRPC server: {}", err))
 }
@@ -0,0 +1,327 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+use tonic::{Request, Status, metadata::MetadataMap, service::Interceptor};
+use tracing::warn;
+
+use crate::config::DispatchAuthConfig;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const AUDIT_TARGET: &str = "audit";
+
+const AUTHORIZATION_KEY: &str = "authorization";
+const DISPATCHER_ID_KEY: &str = "x-dispatcher-id";
+const TIMESTAMP_KEY: &str = "x-dispatch-timestamp";
+const SIGNATURE_KEY: &str = "x-dispatch-signature";
+const NONCE_KEY: &str = "x-dispatch-nonce";
+
+#[derive(Debug, Error)]
+enum AuthError {
+    #[error("Missing {0} metadata")]
+    Missing(&'static str),
+    #[error("Malformed {0} metadata")]
+    Malformed(&'static str),
+    #[error("Invalid credentials")]
+    InvalidCredentials,
+    #[error("Timestamp {0} is too far from the connector clock")]
+    ClockSkew(i64),
+    #[error("Nonce already used")]
+    Replayed,
+}
+
+/// Checks the credentials dispatchers send along with every call, rejecting
+/// and auditing the calls without valid ones.
+#[derive(Clone, Debug)]
+pub struct DispatchAuth {
+    config: Option<Arc<DispatchAuthConfig>>,
+    nonces: Arc<NonceCache>,
+}
+
+impl DispatchAuth {
+    pub fn new(config: Option<&DispatchAuthConfig>) -> Self {
+        Self {
+            config: config.cloned().map(Arc::new),
+            nonces: Arc::new(NonceCache::default()),
+        }
+    }
+}
+
+/// Nonces of the signed calls accepted while their timestamp is still within
+/// the allowed skew, past which the timestamp check rejects them anyway.
+#[derive(Debug, Default)]
+struct NonceCache {
+    inner: Mutex<NonceCacheInner>,
+}
+
+#[derive(Debug, Default)]
+struct NonceCacheInner {
+    /// Expiry of every nonce, keyed by dispatcher and nonce.
+    expires_at: HashMap<(String, String), i64>,
+    pruned_at: i64,
+}
+
+impl NonceCache {
+    /// Returns whether the nonce is new, remembering it until `expires_at`.
+    fn insert(&self, dispatcher_id: &str, nonce: &str, expires_at: i64) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+
+        let now = unix_now();
+
+        // Pruning at most once a second keeps busy dispatchers from scanning
+        // the whole cache on every call.
+        if inner.pruned_at < now {
+            inner.expires_at.retain(|_, expires_at| *expires_at >= now);
+            inner.pruned_at = now;
+        }
+
+        inner
+            .expires_at
+            .insert((dispatcher_id.to_string(), nonce.to_string()), expires_at)
+            .is_none_or(|prev_expires_at| prev_expires_at < now)
+    }
+}
+
+impl Interceptor for DispatchAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let Some(config) = &self.config else {
+            return Ok(request);
+        };
+
+        match authenticate(config, &self.nonces, request.metadata()) {
+            Ok(()) => Ok(request),
+            Err(err) => {
+                let dispatcher_id = request
+                    .metadata()
+                    .get(DISPATCHER_ID_KEY)
+                    .and_then(|value| value.to_str().ok());
+
+                warn!(
+                    target: AUDIT_TARGET,
+                    peer = ?request.remote_addr(),
+                    dispatcher_id = ?dispatcher_id,
+                    "Rejected dispatch call: {}",
+                    err
+                );
+
+                Err(Status::unauthenticated(err.to_string()))
+            }
+        }
+    }
+}
+
+fn authenticate(
+    config: &DispatchAuthConfig,
+    nonces: &NonceCache,
+    metadata: &MetadataMap,
+) -> Result<(), AuthError> {
+    match config {
+        DispatchAuthConfig::SharedSecret { secret } => {
+            let token = metadata_str(metadata, AUTHORIZATION_KEY)?
+                .strip_prefix("Bearer ")
+                .ok_or(AuthError::Malformed(AUTHORIZATION_KEY))?;
+
+            // Comparing MACs of both keeps the comparison constant time.
+            mac(secret, token.as_bytes())
+                .verify_slice(&mac(secret, secret.as_bytes()).finalize().into_bytes())
+                .map_err(|_| AuthError::InvalidCredentials)
+        }
+        DispatchAuthConfig::Hmac {
+            secret,
+            max_skew_secs,
+        } => {
+            let dispatcher_id = metadata_str(metadata, DISPATCHER_ID_KEY)?;
+            let timestamp = metadata_str(metadata, TIMESTAMP_KEY)?;
+            let nonce = metadata_str(metadata, NONCE_KEY)?;
+            let signature = URL_SAFE_NO_PAD
+                .decode(metadata_str(metadata, SIGNATURE_KEY)?)
+                .map_err(|_| AuthError::Malformed(SIGNATURE_KEY))?;
+
+            let payload = format!("{}\n{}\n{}", dispatcher_id, timestamp, nonce);
+
+            mac(secret, payload.as_bytes())
+                .verify_slice(&signature)
+                .map_err(|_| AuthError::InvalidCredentials)?;
+
+            // Checked once the signature holds, so the timestamp can be trusted.
+            let timestamp: i64 = timestamp
+                .parse()
+                .map_err(|_| AuthError::Malformed(TIMESTAMP_KEY))?;
+
+            if unix_now().abs_diff(timestamp) > *max_skew_secs {
+                return Err(AuthError::ClockSkew(timestamp));
+            }
+
+            let expires_at = timestamp.saturating_add_unsigned(*max_skew_secs);
+
+            if !nonces.insert(dispatcher_id, nonce, expires_at) {
+                return Err(AuthError::Replayed);
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn metadata_str<'a>(metadata: &'a MetadataMap, key: &'static str) -> Result<&'a str, AuthError> {
+    metadata
+        .get(key)
+        .ok_or(AuthError::Missing(key))?
+        .to_str()
+        .map_err(|_| AuthError::Malformed(key))
+}
+
+fn mac(secret: &str, payload: &[u8]) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+
+    mac.update(payload);
+
+    mac
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::metadata::MetadataValue;
+
+    use super::*;
+
+    const SECRET: &str = "dispatch-secret";
+
+    fn shared_secret() -> DispatchAuthConfig {
+        DispatchAuthConfig::SharedSecret {
+            secret: SECRET.to_string(),
+        }
+    }
+
+    fn hmac() -> DispatchAuthConfig {
+        DispatchAuthConfig::Hmac {
+            secret: SECRET.to_string(),
+            max_skew_secs: 60,
+        }
+    }
+
+    fn metadata(entries: &[(&'static str, &str)]) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+
+        for (key, value) in entries {
+            metadata.insert(*key, MetadataValue::try_from(*value).unwrap());
+        }
+
+        metadata
+    }
+
+    fn signed(dispatcher_id: &str, timestamp: i64, nonce: &str) -> MetadataMap {
+        let timestamp = timestamp.to_string();
+
+        let payload = format!("{}\n{}\n{}", dispatcher_id, timestamp, nonce);
+        let signature =
+            URL_SAFE_NO_PAD.encode(mac(SECRET, payload.as_bytes()).finalize().into_bytes());
+
+        metadata(&[
+            (DISPATCHER_ID_KEY, dispatcher_id),
+            (TIMESTAMP_KEY, &timestamp),
+            (NONCE_KEY, nonce),
+            (SIGNATURE_KEY, &signature),
+        ])
+    }
+
+    #[test]
+    fn shared_secret_requires_the_bearer_secret() {
+        let nonces = NonceCache::default();
+        let config = shared_secret();
+
+        let valid = metadata(&[(AUTHORIZATION_KEY, "Bearer dispatch-secret")]);
+        assert!(authenticate(&config, &nonces, &valid).is_ok());
+
+        let wrong = metadata(&[(AUTHORIZATION_KEY, "Bearer other-secret")]);
+        assert!(matches!(
+            authenticate(&config, &nonces, &wrong),
+            Err(AuthError::InvalidCredentials)
+        ));
+
+        let malformed = metadata(&[(AUTHORIZATION_KEY, "dispatch-secret")]);
+        assert!(matches!(
+            authenticate(&config, &nonces, &malformed),
+            Err(AuthError::Malformed(AUTHORIZATION_KEY))
+        ));
+
+        assert!(matches!(
+            authenticate(&config, &nonces, &MetadataMap::new()),
+            Err(AuthError::Missing(AUTHORIZATION_KEY))
+        ));
+    }
+
+    #[test]
+    fn hmac_accepts_a_fresh_signature() {
+        let metadata = signed("dispatcher-1", unix_now(), "nonce-1");
+
+        assert!(authenticate(&hmac(), &NonceCache::default(), &metadata).is_ok());
+    }
+
+    #[test]
+    fn hmac_rejects_tampered_metadata() {
+        let mut metadata = signed("dispatcher-1", unix_now(), "nonce-1");
+
+        metadata.insert(
+            DISPATCHER_ID_KEY,
+            MetadataValue::from_static("dispatcher-2"),
+        );
+
+        assert!(matches!(
+            authenticate(&hmac(), &NonceCache::default(), &metadata),
+            Err(AuthError::InvalidCredentials)
+        ));
+    }
+
+    #[test]
+    fn hmac_rejects_skewed_timestamps() {
+        let metadata = signed("dispatcher-1", unix_now() - 120, "nonce-1");
+
+        assert!(matches!(
+            authenticate(&hmac(), &NonceCache::default(), &metadata),
+            Err(AuthError::ClockSkew(_))
+        ));
+    }
+
+    #[test]
+    fn hmac_rejects_replayed_nonces() {
+        let nonces = NonceCache::default();
+        let now = unix_now();
+
+        assert!(authenticate(&hmac(), &nonces, &signed("dispatcher-1", now, "nonce-1")).is_ok());
+        assert!(matches!(
+            authenticate(&hmac(), &nonces, &signed("dispatcher-1", now, "nonce-1")),
+            Err(AuthError::Replayed)
+        ));
+
+        // Nonces are only unique per dispatcher.
+        assert!(authenticate(&hmac(), &nonces, &signed("dispatcher-2", now, "nonce-1")).is_ok());
+        assert!(authenticate(&hmac(), &nonces, &signed("dispatcher-1", now, "nonce-2")).is_ok());
+    }
+
+    #[test]
+    fn hmac_requires_a_nonce() {
+        let mut metadata = signed("dispatcher-1", unix_now(), "nonce-1");
+
+        metadata.remove(NONCE_KEY);
+
+        assert!(matches!(
+            authenticate(&hmac(), &NonceCache::default(), &metadata),
+            Err(AuthError::Missing(NONCE_KEY))
+        ));
+    }
+}
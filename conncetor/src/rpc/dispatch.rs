@@ -1,15 +1,26 @@
-use tonic::{Request, Response, Status};
+use crossfire::{MAsyncTx, TrySendError};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{debug, warn};
 
 pub mod dispatcher {
     tonic::include_proto!("dispatch_service");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("dispatch_service_descriptor");
 }
 
 use dispatcher::{
-    DispatchMessageRequest, DispatchMessageResponse, dispatch_service_server::DispatchService,
+    DispatchMessageRequest, DispatchMessageResponse, DispatchMessageResult,
+    DispatchMulticastRequest, DispatchMulticastResponse, DispatchStatus, RecipientResult,
+    dispatch_service_server::DispatchService,
 };
 
 use crate::{
-    message::ServiceMessage, model::dto::DispatchedMessage, registry::ConsulRegistry,
+    cache::{USER_CONNECTOR_KEY, connector_token},
+    message::ServiceMessage,
+    model::dto::DispatchedMessage,
+    service::{RspMessage, queue_offline, serialize_rsp},
     state::AppState,
 };
 
@@ -25,8 +36,14 @@ impl DispatchServer {
     }
 }
 
+/// Results waiting to be read by the dispatcher. Once full, the requests of the
+/// stream are no longer read, pushing back on the dispatcher.
+const STREAM_RESULT_BUFFER: usize = 256;
+
 #[tonic::async_trait]
 impl DispatchService for DispatchServer {
+    type DispatchMessageStreamStream = ReceiverStream<Result<DispatchMessageResult, Status>>;
+
     async fn dispatch_message(
         &self,
         request: Request<DispatchMessageRequest>,
@@ -36,18 +53,25 @@ impl DispatchService for DispatchServer {
         let user_serv_snd = match self.app_state.online_users().get(&request.target_user_id) {
             Some(tx) => tx,
             None => {
-                return Err(Status::not_found("Target user is not online"));
+                let target_user_id = request.target_user_id.clone();
+
+                return match dispatch_offline(
+                    &self.app_state,
+                    target_user_id,
+                    dispatched_message(request),
+                )
+                .await
+                {
+                    DispatchStatus::QueuedOffline => {
+                        Ok(Response::new(DispatchMessageResponse { successful: true }))
+                    }
+                    _ => Err(Status::not_found("Target user is not online")),
+                };
             }
         };
 
         match user_serv_snd
-            .send(ServiceMessage::DispatchMessage(DispatchedMessage {
-                message_id: request.message_id,
-                user_id: request.user_id,
-                channel_id: request.channel_id,
-                content: request.content,
-                timestamp: request.created_at,
-            }))
+            .send(ServiceMessage::DispatchMessage(dispatched_message(request)))
             .await
         {
             Ok(_) => Ok(Response::new(DispatchMessageResponse { successful: true })),
@@ -57,4 +81,364 @@ impl DispatchService for DispatchServer {
             ))),
         }
     }
+
+    async fn dispatch_message_stream(
+        &self,
+        request: Request<Streaming<DispatchMessageRequest>>,
+    ) -> Result<Response<Self::DispatchMessageStreamStream>, Status> {
+        let mut requests = request.into_inner();
+
+        let (result_tx, result_rx) = tokio::sync::mpsc::channel(STREAM_RESULT_BUFFER);
+
+        let app_state = self.app_state.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let request = match requests.message().await {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    Err(status) => {
+                        debug!("Dispatch stream closed by the dispatcher: {}", status);
+                        break;
+                    }
+                };
+
+                let result = try_dispatch(&app_state, request).await;
+
+                if result_tx.send(Ok(result)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(result_rx)))
+    }
+
+    async fn dispatch_multicast(
+        &self,
+        request: Request<DispatchMulticastRequest>,
+    ) -> Result<Response<DispatchMulticastResponse>, Status> {
+        let request = request.into_inner();
+
+        let message = DispatchedMessage {
+            message_id: request.message_id,
+            user_id: request.user_id,
+            channel_id: request.channel_id,
+            content: request.content,
+            timestamp: request.created_at,
+        };
+
+        let payload = serialize_rsp(&RspMessage::DispatchMessage(message))
+            .map_err(|err| Status::internal(format!("Failed to serialize message: {}", err)))?;
+
+        let capacity = self.app_state.config().user_queue_capacity();
+
+        let mut results = Vec::with_capacity(request.target_user_ids.len());
+        let mut not_connected = Vec::new();
+
+        for target_user_id in request.target_user_ids {
+            let (status, _) = try_queue(
+                self.app_state
+                    .online_users()
+                    .get(&target_user_id)
+                    .as_deref(),
+                ServiceMessage::Serialized(payload.clone()),
+                capacity,
+            );
+
+            // A connected user with a full queue is reported as such for the
+            // dispatcher to retry, rather than stored until it reconnects.
+            match status {
+                DispatchStatus::NotFound => not_connected.push(target_user_id),
+                status => results.push(RecipientResult {
+                    target_user_id,
+                    status: status.into(),
+                }),
+            }
+        }
+
+        let (offline, not_found) =
+            queue_offline_users(&self.app_state, not_connected, payload.as_str()).await;
+
+        let recipient = |status: DispatchStatus| {
+            move |target_user_id| RecipientResult {
+                target_user_id,
+                status: status.into(),
+            }
+        };
+
+        results.extend(
+            offline
+                .into_iter()
+                .map(recipient(DispatchStatus::QueuedOffline)),
+        );
+        results.extend(
+            not_found
+                .into_iter()
+                .map(recipient(DispatchStatus::NotFound)),
+        );
+
+        Ok(Response::new(DispatchMulticastResponse { results }))
+    }
+}
+
+/// Stores `payload` for the users that are not connected to any connector,
+/// returning those it was stored for and those left not found.
+///
+/// Every user is left not found while the offline queue is off.
+async fn queue_offline_users(
+    app_state: &AppState,
+    user_ids: Vec<String>,
+    payload: &str,
+) -> (Vec<String>, Vec<String>) {
+    if app_state.config().offline_queue_len() == 0 || user_ids.is_empty() {
+        return (Vec::new(), user_ids);
+    }
+
+    let (mut offline, mut not_found) = split_offline(app_state, user_ids).await;
+
+    // Failing the whole call would have the delivered users receive the
+    // message again on retry.
+    if let Err(err) = queue_offline(app_state.cache(), app_state.config(), &offline, payload).await
+    {
+        warn!(
+            "Failed to queue message offline, reporting its users as not found: {}",
+            err
+        );
+
+        not_found.append(&mut offline);
+    }
+
+    (offline, not_found)
+}
+
+/// Stores `message` for its target user if the user is not connected to any
+/// connector, the same way multicast messages are.
+async fn dispatch_offline(
+    app_state: &AppState,
+    target_user_id: String,
+    message: DispatchedMessage,
+) -> DispatchStatus {
+    if app_state.config().offline_queue_len() == 0 {
+        return DispatchStatus::NotFound;
+    }
+
+    let payload = match serialize_rsp(&RspMessage::DispatchMessage(message)) {
+        Ok(payload) => payload,
+        Err(err) => {
+            warn!("Failed to serialize message to queue offline: {}", err);
+
+            return DispatchStatus::NotFound;
+        }
+    };
+
+    match queue_offline_users(app_state, vec![target_user_id], payload.as_str()).await {
+        (offline, _) if !offline.is_empty() => DispatchStatus::QueuedOffline,
+        _ => DispatchStatus::NotFound,
+    }
+}
+
+/// Splits the users not connected to this connector into those the message
+/// should be stored for, and those connected to another connector.
+async fn split_offline(app_state: &AppState, user_ids: Vec<String>) -> (Vec<String>, Vec<String>) {
+    let config = app_state.config();
+    let own_token = connector_token(config.service_name(), config.service_id());
+
+    match app_state
+        .cache()
+        .hash_get_many(USER_CONNECTOR_KEY, &user_ids)
+        .await
+    {
+        Ok(owners) => partition_offline(user_ids, owners, &own_token),
+        Err(err) => {
+            warn!("Failed to look up the connectors of users: {}", err);
+
+            (Vec::new(), user_ids)
+        }
+    }
+}
+
+/// Partitions users by the connector token they are registered with in
+/// `owners`, in the same order.
+///
+/// A user is offline when not registered anywhere, or when still registered
+/// with this connector while its connection closes.
+fn partition_offline(
+    user_ids: Vec<String>,
+    owners: Vec<Option<String>>,
+    own_token: &str,
+) -> (Vec<String>, Vec<String>) {
+    let (offline, not_found): (Vec<_>, Vec<_>) = user_ids
+        .into_iter()
+        .zip(owners)
+        .partition(|(_, owner)| owner.as_deref().is_none_or(|owner| owner == own_token));
+
+    (
+        offline.into_iter().map(|(user_id, _)| user_id).collect(),
+        not_found.into_iter().map(|(user_id, _)| user_id).collect(),
+    )
+}
+
+/// Queues the message for its target user without waiting, so that a user with
+/// a full queue does not hold up the messages of the others in the stream.
+///
+/// Users not connected anywhere get the message stored offline, if enabled.
+async fn try_dispatch(
+    app_state: &AppState,
+    request: DispatchMessageRequest,
+) -> DispatchMessageResult {
+    let message_id = request.message_id.clone();
+    let target_user_id = request.target_user_id.clone();
+    let message = dispatched_message(request);
+
+    // Kept to be stored if the user turns out not to be connected.
+    let offline_copy = (app_state.config().offline_queue_len() > 0).then(|| message.clone());
+
+    let (mut status, queue_available) = try_queue(
+        app_state.online_users().get(&target_user_id).as_deref(),
+        ServiceMessage::DispatchMessage(message),
+        app_state.config().user_queue_capacity(),
+    );
+
+    if let (DispatchStatus::NotFound, Some(message)) = (status, offline_copy) {
+        status = dispatch_offline(app_state, target_user_id.clone(), message).await;
+    }
+
+    DispatchMessageResult {
+        message_id,
+        target_user_id,
+        status: status.into(),
+        queue_available: queue_available as u32,
+    }
+}
+
+/// Queues `message` on the connection of a user, if connected, returning the
+/// outcome and the free slots left out of `capacity`.
+fn try_queue(
+    user_serv_snd: Option<&MAsyncTx<ServiceMessage>>,
+    message: ServiceMessage,
+    capacity: usize,
+) -> (DispatchStatus, usize) {
+    let Some(user_serv_snd) = user_serv_snd else {
+        return (DispatchStatus::NotFound, 0);
+    };
+
+    match user_serv_snd.try_send(message) {
+        Ok(()) => (
+            DispatchStatus::Queued,
+            capacity.saturating_sub(user_serv_snd.len()),
+        ),
+        Err(TrySendError::Full(_)) => (DispatchStatus::QueueFull, 0),
+        // The connection is closing.
+        Err(TrySendError::Disconnected(_)) => (DispatchStatus::NotFound, 0),
+    }
+}
+
+fn dispatched_message(request: DispatchMessageRequest) -> DispatchedMessage {
+    DispatchedMessage {
+        message_id: request.message_id,
+        user_id: request.user_id,
+        channel_id: request.channel_id,
+        content: request.content,
+        timestamp: request.created_at,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossfire::mpsc;
+
+    use super::*;
+
+    fn message() -> ServiceMessage {
+        ServiceMessage::Serialized("{}".to_string().into())
+    }
+
+    #[test]
+    fn queues_for_connected_users() {
+        let (tx, _rx) = mpsc::bounded_async(4);
+
+        assert_eq!(
+            try_queue(Some(&tx), message(), 4),
+            (DispatchStatus::Queued, 3)
+        );
+        assert_eq!(
+            try_queue(Some(&tx), message(), 4),
+            (DispatchStatus::Queued, 2)
+        );
+    }
+
+    #[test]
+    fn reports_full_queues() {
+        let (tx, _rx) = mpsc::bounded_async(2);
+
+        try_queue(Some(&tx), message(), 2);
+        assert_eq!(
+            try_queue(Some(&tx), message(), 2),
+            (DispatchStatus::Queued, 0)
+        );
+
+        assert_eq!(
+            try_queue(Some(&tx), message(), 2),
+            (DispatchStatus::QueueFull, 0)
+        );
+    }
+
+    #[test]
+    fn reports_missing_users_as_not_found() {
+        assert_eq!(try_queue(None, message(), 4), (DispatchStatus::NotFound, 0));
+    }
+
+    fn users(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn users_registered_nowhere_are_offline() {
+        let (offline, not_found) = partition_offline(
+            users(&["alice", "bob"]),
+            vec![None, Some("connector:other".to_string())],
+            "connector:own",
+        );
+
+        assert_eq!(offline, ["alice"]);
+        assert_eq!(not_found, ["bob"]);
+    }
+
+    #[test]
+    fn users_still_registered_here_are_offline() {
+        // Their connection is closing, and the next one may land anywhere.
+        let (offline, not_found) = partition_offline(
+            users(&["alice", "bob", "carol"]),
+            vec![
+                Some("connector:own".to_string()),
+                Some("connector:other".to_string()),
+                None,
+            ],
+            "connector:own",
+        );
+
+        assert_eq!(offline, ["alice", "carol"]);
+        assert_eq!(not_found, ["bob"]);
+    }
+
+    #[test]
+    fn partitions_no_users() {
+        let (offline, not_found) = partition_offline(Vec::new(), Vec::new(), "connector:own");
+
+        assert!(offline.is_empty());
+        assert!(not_found.is_empty());
+    }
+
+    #[test]
+    fn reports_closing_connections_as_not_found() {
+        let (tx, rx) = mpsc::bounded_async(4);
+
+        drop(rx);
+
+        assert_eq!(
+            try_queue(Some(&tx), message(), 4),
+            (DispatchStatus::NotFound, 0)
+        );
+    }
 }
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use tonic::server::NamedService;
+use tonic_health::{ServingStatus, server::HealthReporter};
+use tracing::{info, warn};
+
+use crate::{
+    rpc::dispatch::{DispatchServer, dispatcher::dispatch_service_server::DispatchServiceServer},
+    service,
+    state::AppState,
+};
+
+const HEALTH_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Name of the dispatch service in the health checking protocol.
+pub const DISPATCH_SERVICE_NAME: &str =
+    <DispatchServiceServer<DispatchServer> as NamedService>::NAME;
+
+/// Keeps the gRPC health status of the server and of the dispatch service in
+/// line with the readiness of the connector.
+///
+/// Draining is reported as soon as it starts, so that clients stop picking the
+/// connector for the whole drain grace period.
+pub fn spawn_health_reporter(state: AppState, reporter: HealthReporter) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEALTH_REPORT_INTERVAL);
+        let mut draining = state.subscribe_draining();
+        let mut last_status = None;
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                Ok(()) = draining.changed() => {}
+            }
+
+            // Draining alone decides, without waiting on the cache ping.
+            let reasons = match state.is_draining() {
+                true => vec!["Draining".to_string()],
+                false => service::unready_reasons(&state).await,
+            };
+
+            let status = match reasons.is_empty() {
+                true => ServingStatus::Serving,
+                false => ServingStatus::NotServing,
+            };
+
+            reporter.set_service_status("", status).await;
+            reporter
+                .set_service_status(DISPATCH_SERVICE_NAME, status)
+                .await;
+
+            if last_status != Some(status) {
+                match reasons.is_empty() {
+                    true => info!("gRPC health status is {:?}", status),
+                    false => warn!("gRPC health status is {:?}: {}", status, reasons.join("; ")),
+                }
+
+                last_status = Some(status);
+            }
+        }
+    });
+}
@@ -1,10 +1,26 @@
+mod admin;
+mod announce;
+mod assign;
 mod channel;
 mod connect;
+mod context;
 mod health;
 mod message;
+mod offline;
 mod result;
+mod retry;
+mod ticket;
+mod upstream;
 mod user;
 
+pub use admin::*;
+pub use announce::*;
+pub use assign::*;
 pub use connect::*;
+pub use context::{CallContext, CallInterceptor, Session};
 pub use health::*;
+pub use offline::{Outbox, queue_offline, take_offline};
 pub use result::*;
+pub use retry::{RetryBudget, RetryBudgetConfig, RetryPolicy};
+pub use ticket::verify_ticket;
+pub use upstream::{RoutingKey, UpstreamRpc};
@@ -0,0 +1,170 @@
+use std::fmt::Write as _;
+
+use tonic::transport::Channel;
+
+use crate::{
+    model::dto::{InstanceReport, UpstreamReport, UpstreamsRsp},
+    registry::{ServiceRegistry, store::Store},
+    service::{ServiceResult, succeed},
+    state::AppState,
+};
+
+/// Reports the refresh health of every upstream registry, and the load and
+/// circuit breaker state of each of its instances.
+pub async fn list_upstreams(state: &AppState) -> ServiceResult<UpstreamsRsp> {
+    let upstreams = state
+        .upstream_registries()
+        .into_iter()
+        .map(upstream_report)
+        .collect();
+
+    Ok(succeed().with_data(UpstreamsRsp { upstreams }))
+}
+
+fn upstream_report(registry: &ServiceRegistry<Channel>) -> UpstreamReport {
+    let status = registry.refresh_status();
+
+    let mut instances: Vec<InstanceReport> = registry
+        .store()
+        .list()
+        .into_iter()
+        .map(|data| InstanceReport {
+            id: data.entry().info().id().to_string(),
+            address: data.entry().info().address(),
+            in_flight: data.in_flight(),
+            breaker: data.breaker().state(),
+        })
+        .collect();
+
+    instances.sort_by(|a, b| a.id.cmp(&b.id));
+
+    UpstreamReport {
+        service: registry.service_prefix().to_string(),
+        degraded: registry.is_degraded(),
+        stale: registry.is_stale(),
+        last_refresh_secs_ago: status.last_success().map(|at| at.elapsed().as_secs()),
+        consecutive_refresh_errors: status.consecutive_errors(),
+        total_refresh_errors: status.total_errors(),
+        instances,
+    }
+}
+
+/// Renders the connector metrics in the Prometheus text format.
+pub async fn render_metrics(state: &AppState) -> String {
+    let mut out = String::new();
+
+    let registries = state.upstream_registries();
+
+    metric_header(
+        &mut out,
+        "connector_websocket_connections",
+        "gauge",
+        "Open WebSocket connections.",
+    );
+    let _ = writeln!(
+        out,
+        "connector_websocket_connections {}",
+        state.online_users().len()
+    );
+
+    metric_header(
+        &mut out,
+        "connector_upstream_stale",
+        "gauge",
+        "Whether the instances of the upstream are stale.",
+    );
+    for registry in registries {
+        let _ = writeln!(
+            out,
+            "connector_upstream_stale{{upstream=\"{}\"}} {}",
+            label(registry.service_prefix()),
+            registry.is_stale() as u8
+        );
+    }
+
+    metric_header(
+        &mut out,
+        "connector_upstream_refresh_errors_total",
+        "counter",
+        "Failed refreshes of the upstream instances.",
+    );
+    for registry in registries {
+        let _ = writeln!(
+            out,
+            "connector_upstream_refresh_errors_total{{upstream=\"{}\"}} {}",
+            label(registry.service_prefix()),
+            registry.refresh_status().total_errors()
+        );
+    }
+
+    metric_header(
+        &mut out,
+        "connector_upstream_instance_in_flight",
+        "gauge",
+        "Outstanding calls per upstream instance.",
+    );
+    for registry in registries {
+        for data in registry.store().list() {
+            let _ = writeln!(
+                out,
+                "connector_upstream_instance_in_flight{{upstream=\"{}\",instance=\"{}\"}} {}",
+                label(registry.service_prefix()),
+                label(data.entry().info().id()),
+                data.in_flight()
+            );
+        }
+    }
+
+    metric_header(
+        &mut out,
+        "connector_upstream_instance_breaker_state",
+        "gauge",
+        "Circuit breaker state per upstream instance: 0 closed, 1 half-open, 2 open.",
+    );
+    for registry in registries {
+        for data in registry.store().list() {
+            let _ = writeln!(
+                out,
+                "connector_upstream_instance_breaker_state{{upstream=\"{}\",instance=\"{}\"}} {}",
+                label(registry.service_prefix()),
+                label(data.entry().info().id()),
+                data.breaker().state().as_gauge()
+            );
+        }
+    }
+
+    out
+}
+
+fn metric_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value of the Prometheus text format.
+fn label(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(label("user-1"), "user-1");
+        assert_eq!(label(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(label("a\nb"), r"a\nb");
+    }
+}
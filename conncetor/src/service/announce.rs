@@ -0,0 +1,103 @@
+use std::{collections::HashMap, time::Duration};
+
+use tracing::{debug, warn};
+
+use crate::{
+    registry::model::{HeathCheck, Registry},
+    state::AppState,
+};
+
+pub const CONNECTIONS_META_KEY: &str = "connections";
+pub const CAPACITY_META_KEY: &str = "capacity";
+pub const WEBSOCK_PATH_META_KEY: &str = "ws_path";
+pub const WEBSOCK_SCHEME_META_KEY: &str = "ws_scheme";
+
+const WEBSOCK_TAG: &str = "websocket";
+pub const WEBSOCK_PATH: &str = "/ws/{user_id}";
+
+const LOAD_REPORT_INTERVAL_SECS: u64 = 15;
+
+/// Registers the WebSocket endpoint of this connector, and keeps its load in
+/// the registered metadata up to date so the front door can pick the least
+/// loaded connector.
+pub async fn announce_websock_endpoint(state: &AppState) -> anyhow::Result<()> {
+    let ttl = Duration::from_secs(state.config().refresh_ttl_secs());
+
+    state
+        .discovery()
+        .register(websock_registry(state, ttl, false), ttl)
+        .await?;
+
+    let state = state.clone();
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(LOAD_REPORT_INTERVAL_SECS));
+
+        // The first tick completes immediately, right after the registration.
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            // The keepalive is already running, so report the check as passing
+            // rather than letting Consul reset it to critical.
+            let service = websock_registry(&state, ttl, true);
+
+            match state.discovery().reregister(service).await {
+                Ok(()) => debug!(
+                    "Reported {} WebSocket connections",
+                    state.online_users().len()
+                ),
+                Err(err) => warn!("Failed to report WebSocket load: {}", err),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn websock_registry(state: &AppState, ttl: Duration, passing: bool) -> Registry {
+    let config = state.config();
+
+    let service_name = config.websock_service_name();
+    let service_id = config.websock_service_id();
+
+    let mut tags = config.service_tags().to_vec();
+    tags.push(WEBSOCK_TAG.to_string());
+
+    let meta = HashMap::from([
+        (
+            CONNECTIONS_META_KEY.to_string(),
+            state.online_users().len().to_string(),
+        ),
+        (
+            CAPACITY_META_KEY.to_string(),
+            config.max_connections().to_string(),
+        ),
+        (WEBSOCK_PATH_META_KEY.to_string(), WEBSOCK_PATH.to_string()),
+        (
+            WEBSOCK_SCHEME_META_KEY.to_string(),
+            if config.websock_tls() { "wss" } else { "ws" }.to_string(),
+        ),
+    ]);
+
+    let mut check = HeathCheck::new(
+        ttl,
+        format!("{}-{}", service_name, service_id),
+        service_name.clone(),
+    );
+
+    if passing {
+        check = check.with_status("passing");
+    }
+
+    Registry::new(
+        service_id,
+        service_name,
+        config.http_host().to_string(),
+        config.http_port(),
+        check,
+    )
+    .with_tags(tags)
+    .with_meta(meta)
+}
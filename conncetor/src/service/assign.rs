@@ -0,0 +1,208 @@
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use tracing::{debug, warn};
+
+use crate::{
+    config::AssignPolicy,
+    model::dto::{AssignConnectorReq, AssignConnectorRsp},
+    registry::{
+        model::ServiceInfo,
+        store::{ServiceData, Store},
+    },
+    service::{
+        CAPACITY_META_KEY, CONNECTIONS_META_KEY, ServiceError, ServiceResult, WEBSOCK_PATH,
+        WEBSOCK_PATH_META_KEY, WEBSOCK_SCHEME_META_KEY, succeed,
+        ticket::{issue_ticket, verify_bearer},
+    },
+    state::AppState,
+};
+
+/// Characters escaped in a URL path segment, the path separator included.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Picks the connector `args.user_id` should open its WebSocket on, among the
+/// healthy connectors that still have room.
+///
+/// A ticket lets anyone connect as `args.user_id`, so while tickets are signed
+/// only backends presenting the `assign_token` in `authorization` are served.
+pub async fn assign_connector(
+    args: AssignConnectorReq,
+    authorization: Option<&str>,
+    state: &AppState,
+) -> ServiceResult<AssignConnectorRsp> {
+    if state.config().ticket_secret().is_some()
+        && !state
+            .config()
+            .assign_token()
+            .is_some_and(|token| verify_bearer(token, authorization))
+    {
+        warn!(
+            "Refusing to assign a connector to user {}: caller not authenticated",
+            args.user_id
+        );
+
+        return Err(ServiceError::Unauthenticated);
+    }
+
+    let store = state.connector_registry().store();
+
+    let connector = match state.config().assign_policy() {
+        AssignPolicy::LeastLoaded => store
+            .list()
+            .into_iter()
+            .filter(has_room)
+            .min_by(|a, b| load(a).total_cmp(&load(b))),
+        AssignPolicy::Sticky => {
+            let count = store.list().len();
+
+            // Walk past the owner of the user if it is full.
+            store
+                .candidates(&args.user_id, count)
+                .into_iter()
+                .find(has_room)
+        }
+    }
+    .ok_or(ServiceError::NoConnectorAvailable)?;
+
+    let info = connector.entry().info();
+
+    let url = websock_url(info, &args.user_id);
+
+    debug!("Assigned user {} to connector {}", args.user_id, info.id());
+
+    let (ticket, expires_at) = match state.config().ticket_secret() {
+        Some(secret) => {
+            let (ticket, expires_at) = issue_ticket(
+                secret,
+                &args.user_id,
+                info.id(),
+                state.config().ticket_ttl(),
+            );
+
+            (Some(ticket), Some(expires_at))
+        }
+        None => (None, None),
+    };
+
+    Ok(succeed().with_data(AssignConnectorRsp {
+        connector_id: info.id().to_string(),
+        url,
+        ticket,
+        expires_at,
+    }))
+}
+
+/// URL of the WebSocket endpoint a connector advertises, for `user_id`.
+fn websock_url(info: &ServiceInfo, user_id: &str) -> String {
+    let scheme = info
+        .meta()
+        .get(WEBSOCK_SCHEME_META_KEY)
+        .map_or("ws", String::as_str);
+
+    let path = info
+        .meta()
+        .get(WEBSOCK_PATH_META_KEY)
+        .map_or(WEBSOCK_PATH, String::as_str)
+        .replace(
+            "{user_id}",
+            &utf8_percent_encode(user_id, PATH_SEGMENT).to_string(),
+        );
+
+    format!("{}://{}{}", scheme, info.address(), path)
+}
+
+fn meta_count(connector: &ServiceData<()>, key: &str) -> Option<usize> {
+    connector
+        .entry()
+        .info()
+        .meta()
+        .get(key)
+        .and_then(|value| value.parse().ok())
+}
+
+fn has_room(connector: &ServiceData<()>) -> bool {
+    match (
+        meta_count(connector, CONNECTIONS_META_KEY),
+        meta_count(connector, CAPACITY_META_KEY),
+    ) {
+        (Some(connections), Some(capacity)) => connections < capacity,
+        // Connectors not reporting their load are not excluded.
+        _ => true,
+    }
+}
+
+/// Share of its capacity a connector uses, connectors not reporting their load
+/// coming last.
+fn load(connector: &ServiceData<()>) -> f64 {
+    match (
+        meta_count(connector, CONNECTIONS_META_KEY),
+        meta_count(connector, CAPACITY_META_KEY),
+    ) {
+        (Some(connections), Some(capacity)) if capacity > 0 => connections as f64 / capacity as f64,
+        _ => f64::MAX,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn connector(meta: &[(&str, &str)]) -> ServiceInfo {
+        ServiceInfo::new(
+            "connector-ws".to_string(),
+            "connector-ws".to_string(),
+            "10.0.0.1".to_string(),
+            8080,
+        )
+        .with_meta(
+            meta.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    #[test]
+    fn builds_the_advertised_url() {
+        let info = connector(&[
+            (WEBSOCK_PATH_META_KEY, "/chat/{user_id}/ws"),
+            (WEBSOCK_SCHEME_META_KEY, "wss"),
+        ]);
+
+        assert_eq!(
+            websock_url(&info, "user-1"),
+            "wss://10.0.0.1:8080/chat/user-1/ws"
+        );
+    }
+
+    #[test]
+    fn defaults_to_plain_websocket_on_the_default_path() {
+        assert_eq!(
+            websock_url(&connector(&[]), "user-1"),
+            "ws://10.0.0.1:8080/ws/user-1"
+        );
+    }
+
+    #[test]
+    fn escapes_the_user_id() {
+        assert_eq!(
+            websock_url(&connector(&[]), "a/b?c#d e%"),
+            "ws://10.0.0.1:8080/ws/a%2Fb%3Fc%23d%20e%25"
+        );
+        assert_eq!(
+            websock_url(&connector(&[]), "üser"),
+            "ws://10.0.0.1:8080/ws/%C3%BCser"
+        );
+    }
+}
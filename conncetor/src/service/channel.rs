@@ -7,33 +7,39 @@ use crate::{
         ChannelDetail, ChannelMember, CreateChannelReq, CreateChannelRsp, JoinChannelReq,
         JoinChannelRsp, ListChannelDetailsReq, ListChannelDetailsRsp,
     },
-    registry::{ConsulRegistry, store::Store},
+    registry::ServiceRegistry,
     service::{
-        ServiceError, ServiceResult,
-        channel::channel_service::channel_service_client::ChannelServiceClient, succeed,
+        CallContext, ServiceResult,
+        channel::channel_service::channel_service_client::ChannelServiceClient,
+        succeed,
+        upstream::{self, RouteKeys, UpstreamRpc},
     },
 };
 use tonic::transport::Channel;
 
 pub async fn create_channel(
     args: CreateChannelReq,
-    registry: &ConsulRegistry<Channel>,
+    registry: &ServiceRegistry<Channel>,
+    ctx: &CallContext,
 ) -> ServiceResult<CreateChannelRsp> {
-    let chan = registry
-        .store()
-        .read()
-        .unwrap()
-        .pick(&args.creator_id)
-        .ok_or_else(|| ServiceError::UpstreamUnaccesibleError)?
-        .extra_data()
-        .clone();
-
-    let mut client = ChannelServiceClient::new(chan);
-
-    let grpc_request =
-        tonic::Request::new(channel_service::CreateChannelRequest { name: args.name });
-
-    let grpc_response = client.create_channel(grpc_request).await?.into_inner();
+    let grpc_request = channel_service::CreateChannelRequest { name: args.name };
+
+    let grpc_response = upstream::call(
+        registry,
+        UpstreamRpc::CreateChannel,
+        RouteKeys::new().with_user(&args.creator_id),
+        ctx,
+        |chan| {
+            let grpc_request = grpc_request.clone();
+
+            async move {
+                ChannelServiceClient::new(chan)
+                    .create_channel(grpc_request)
+                    .await
+            }
+        },
+    )
+    .await?;
 
     Ok(succeed().with_data(CreateChannelRsp {
         channel_id: grpc_response.id,
@@ -43,25 +49,32 @@ pub async fn create_channel(
 
 pub async fn join_channel(
     args: JoinChannelReq,
-    registry: &ConsulRegistry<Channel>,
+    registry: &ServiceRegistry<Channel>,
+    ctx: &CallContext,
 ) -> ServiceResult<JoinChannelRsp> {
-    let chan = registry
-        .store()
-        .read()
-        .unwrap()
-        .pick(&args.user_id)
-        .ok_or_else(|| ServiceError::UpstreamUnaccesibleError)?
-        .extra_data()
-        .clone();
-
-    let mut client = ChannelServiceClient::new(chan);
-
-    let grpc_request = tonic::Request::new(channel_service::JoinChannelRequest {
-        user_id: args.user_id,
-        channel_id: args.channel_id,
-    });
-
-    let grpc_response = client.join_channel(grpc_request).await?.into_inner();
+    let grpc_request = channel_service::JoinChannelRequest {
+        user_id: args.user_id.clone(),
+        channel_id: args.channel_id.clone(),
+    };
+
+    let grpc_response = upstream::call(
+        registry,
+        UpstreamRpc::JoinChannel,
+        RouteKeys::new()
+            .with_user(&args.user_id)
+            .with_channel(&args.channel_id),
+        ctx,
+        |chan| {
+            let grpc_request = grpc_request.clone();
+
+            async move {
+                ChannelServiceClient::new(chan)
+                    .join_channel(grpc_request)
+                    .await
+            }
+        },
+    )
+    .await?;
 
     Ok(succeed().with_data(JoinChannelRsp {
         channel_id: grpc_response.channel_id,
@@ -71,27 +84,29 @@ pub async fn join_channel(
 
 pub async fn list_user_channels(
     args: ListChannelDetailsReq,
-    registry: &ConsulRegistry<Channel>,
+    registry: &ServiceRegistry<Channel>,
+    ctx: &CallContext,
 ) -> ServiceResult<ListChannelDetailsRsp> {
-    let chan = registry
-        .store()
-        .read()
-        .unwrap()
-        .pick(&args.user_id)
-        .ok_or_else(|| ServiceError::UpstreamUnaccesibleError)?
-        .extra_data()
-        .clone();
-
-    let mut client = ChannelServiceClient::new(chan);
-
-    let grpc_request = tonic::Request::new(channel_service::ListChannelDetailRequest {
-        user_id: args.user_id,
-    });
-
-    let grpc_response = client
-        .list_channel_details(grpc_request)
-        .await?
-        .into_inner();
+    let grpc_request = channel_service::ListChannelDetailRequest {
+        user_id: args.user_id.clone(),
+    };
+
+    let grpc_response = upstream::call(
+        registry,
+        UpstreamRpc::ListChannelDetails,
+        RouteKeys::new().with_user(&args.user_id),
+        ctx,
+        |chan| {
+            let grpc_request = grpc_request.clone();
+
+            async move {
+                ChannelServiceClient::new(chan)
+                    .list_channel_details(grpc_request)
+                    .await
+            }
+        },
+    )
+    .await?;
 
     let channels = grpc_response
         .channels
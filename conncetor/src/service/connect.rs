@@ -9,14 +9,15 @@ use tonic::transport::Channel;
 use tracing::error;
 
 use crate::message::ServiceMessage;
+use crate::service::{ServiceError, Session};
 
 use crate::model::dto::{
     CreateChannelReq, CreateChannelRsp, CreateMessageReq, CreateMessageRsp, DispatchedMessage,
-    GetUserInfoReq, GetUserInfoRsp, JoinChannelReq, JoinChannelRsp, ListChannelDetailsReq,
-    ListChannelDetailsRsp, ListMessagesReq, ListMessagesRsp, LoginUserReq, LoginUserRsp,
-    RegisterUserReq, RegisterUserRsp,
+    ErrorRsp, GetUserInfoReq, GetUserInfoRsp, JoinChannelReq, JoinChannelRsp,
+    ListChannelDetailsReq, ListChannelDetailsRsp, ListMessagesReq, ListMessagesRsp, LoginUserReq,
+    LoginUserRsp, RegisterUserReq, RegisterUserRsp,
 };
-use crate::registry::ConsulRegistry;
+use crate::registry::ServiceRegistry;
 use crate::service::user::{register_user, login_user, get_user_info};
 use crate::service::channel::{create_channel, join_channel, list_user_channels};
 use crate::service::message::{create_message, list_channel_messages};
@@ -48,6 +49,13 @@ pub enum RspMessage {
     CreateMessage(CreateMessageRsp),
     ListMessages(ListMessagesRsp),
     DispatchMessage(DispatchedMessage),
+    Error(ErrorRsp),
+}
+
+/// Serializes a response once, so that it can be sent to several users as a
+/// `ServiceMessage::Serialized` sharing the same buffer.
+pub fn serialize_rsp(response: &RspMessage) -> serde_json::Result<ws::Utf8Bytes> {
+    serde_json::to_string(response).map(Into::into)
 }
 
 /// Handle a service message and convert it to a WebSocket message.
@@ -55,6 +63,7 @@ pub enum RspMessage {
 pub async fn handle_serv_message(serv_message: ServiceMessage) -> Option<ws::Message> {
     let response: RspMessage = match serv_message {
         ServiceMessage::Pong => return Some(ws::Message::Pong(Bytes::default())),
+        ServiceMessage::Serialized(text) => return Some(ws::Message::Text(text)),
         ServiceMessage::DispatchMessage(msg) => RspMessage::DispatchMessage(msg),
         ServiceMessage::RegisterUserRsp(rsp) => RspMessage::RegisterUser(rsp),
         ServiceMessage::LoginUserRsp(rsp) => RspMessage::LoginUser(rsp),
@@ -64,6 +73,7 @@ pub async fn handle_serv_message(serv_message: ServiceMessage) -> Option<ws::Mes
         ServiceMessage::JoinChannelRsp(rsp) => RspMessage::JoinChannel(rsp),
         ServiceMessage::CreateMessageRsp(rsp) => RspMessage::CreateMessage(rsp),
         ServiceMessage::ListMessagesRsp(rsp) => RspMessage::ListMessages(rsp),
+        ServiceMessage::ErrorRsp(rsp) => RspMessage::Error(rsp),
     };
 
     let text_content = match serde_json::to_string(&response) {
@@ -79,10 +89,11 @@ pub async fn handle_serv_message(serv_message: ServiceMessage) -> Option<ws::Mes
 
 pub async fn handle_websock_message(
     user_id: &str,
+    session: &Session,
     user_serv_snd: &MAsyncTx<ServiceMessage>,
-    user_registry: &ConsulRegistry<Channel>,
-    channel_registry: &ConsulRegistry<Channel>,
-    message_registry: &ConsulRegistry<Channel>,
+    user_registry: &ServiceRegistry<Channel>,
+    channel_registry: &ServiceRegistry<Channel>,
+    message_registry: &ServiceRegistry<Channel>,
     websock_message: ws::Message,
 ) -> ControlFlow<anyhow::Result<()>> {
     let text_content = match websock_message {
@@ -116,9 +127,11 @@ pub async fn handle_websock_message(
         }
     };
 
+    let ctx = session.call_context();
+
     match request {
         ReqMessage::RegisterUser(req) => {
-            let serv_result = match register_user(req, user_registry).await {
+            let serv_result = match register_user(req, user_registry, &ctx).await {
                 Ok(val) => {
                     user_serv_snd
                         .send(ServiceMessage::RegisterUserRsp(val.data.unwrap()))
@@ -133,7 +146,9 @@ pub async fn handle_websock_message(
                 }
                 Err(err) => {
                     error!("Error registering user for user_id {}: {}", user_id, err);
-                    
+
+                    report_error(user_serv_snd, &err).await;
+
                     return ControlFlow::Continue(());
                 }
             };
@@ -148,7 +163,7 @@ pub async fn handle_websock_message(
             ControlFlow::Continue(())
         }
         ReqMessage::LoginUser(req) => {
-            let serv_result = match login_user(req, user_registry).await {
+            let serv_result = match login_user(req, user_registry, &ctx).await {
                 Ok(val) => {
                     user_serv_snd
                         .send(ServiceMessage::LoginUserRsp(val.data.unwrap()))
@@ -163,6 +178,9 @@ pub async fn handle_websock_message(
                 }
                 Err(err) => {
                     error!("Error logging in user for user_id {}: {}", user_id, err);
+
+                    report_error(user_serv_snd, &err).await;
+
                     return ControlFlow::Continue(());
                 }
             };
@@ -177,7 +195,7 @@ pub async fn handle_websock_message(
             ControlFlow::Continue(())
         }
         ReqMessage::GetUserInfo(req) => {
-            let serv_result = match get_user_info(req, user_registry).await {
+            let serv_result = match get_user_info(req, user_registry, &ctx).await {
                 Ok(val) => {
                     user_serv_snd
                         .send(ServiceMessage::GetUserInfoRsp(val.data.unwrap()))
@@ -192,6 +210,9 @@ pub async fn handle_websock_message(
                 }
                 Err(err) => {
                     error!("Error getting user info for user_id {}: {}", user_id, err);
+
+                    report_error(user_serv_snd, &err).await;
+
                     return ControlFlow::Continue(());
                 }
             };
@@ -206,7 +227,7 @@ pub async fn handle_websock_message(
             ControlFlow::Continue(())
         }
         ReqMessage::CreateChannel(req) => {
-            let serv_result = match create_channel(req, channel_registry).await {
+            let serv_result = match create_channel(req, channel_registry, &ctx).await {
                 Ok(val) => {
                     user_serv_snd
                         .send(ServiceMessage::CreateChannelRsp(val.data.unwrap()))
@@ -221,6 +242,9 @@ pub async fn handle_websock_message(
                 }
                 Err(err) => {
                     error!("Error creating channel for user_id {}: {}", user_id, err);
+
+                    report_error(user_serv_snd, &err).await;
+
                     return ControlFlow::Continue(());
                 }
             };
@@ -235,7 +259,7 @@ pub async fn handle_websock_message(
             ControlFlow::Continue(())
         }
         ReqMessage::ListChannelDetails(req) => {
-            let serv_result = match list_user_channels(req, channel_registry).await {
+            let serv_result = match list_user_channels(req, channel_registry, &ctx).await {
                 Ok(val) => {
                     user_serv_snd
                         .send(ServiceMessage::ListChannelDetailsRsp(val.data.unwrap()))
@@ -250,6 +274,9 @@ pub async fn handle_websock_message(
                 }
                 Err(err) => {
                     error!("Error listing channel details for user_id {}: {}", user_id, err);
+
+                    report_error(user_serv_snd, &err).await;
+
                     return ControlFlow::Continue(());
                 }
             };
@@ -264,7 +291,7 @@ pub async fn handle_websock_message(
             ControlFlow::Continue(())
         }
         ReqMessage::JoinChannel(req) => {
-            let serv_result = match join_channel(req, channel_registry).await {
+            let serv_result = match join_channel(req, channel_registry, &ctx).await {
                 Ok(val) => {
                     user_serv_snd
                         .send(ServiceMessage::JoinChannelRsp(val.data.unwrap()))
@@ -279,6 +306,9 @@ pub async fn handle_websock_message(
                 }
                 Err(err) => {
                     error!("Error joining channel for user_id {}: {}", user_id, err);
+
+                    report_error(user_serv_snd, &err).await;
+
                     return ControlFlow::Continue(());
                 }
             };
@@ -293,7 +323,7 @@ pub async fn handle_websock_message(
             ControlFlow::Continue(())
         }
         ReqMessage::CreateMessage(req) => {
-            let serv_result = match create_message(req, message_registry).await {
+            let serv_result = match create_message(req, message_registry, &ctx).await {
                 Ok(val) => {
                     user_serv_snd
                         .send(ServiceMessage::CreateMessageRsp(val.data.unwrap()))
@@ -308,6 +338,9 @@ pub async fn handle_websock_message(
                 }
                 Err(err) => {
                     error!("Error creating message for user_id {}: {}", user_id, err);
+
+                    report_error(user_serv_snd, &err).await;
+
                     return ControlFlow::Continue(());
                 }
             };
@@ -322,7 +355,7 @@ pub async fn handle_websock_message(
             ControlFlow::Continue(())
         }
         ReqMessage::ListMessages(req) => {
-            let serv_result = match list_channel_messages(req, message_registry).await {
+            let serv_result = match list_channel_messages(req, message_registry, &ctx).await {
                 Ok(val) => {
                     user_serv_snd
                         .send(ServiceMessage::ListMessagesRsp(val.data.unwrap()))
@@ -337,6 +370,9 @@ pub async fn handle_websock_message(
                 }
                 Err(err) => {
                     error!("Error listing messages for user_id {}: {}", user_id, err);
+
+                    report_error(user_serv_snd, &err).await;
+
                     return ControlFlow::Continue(());
                 }
             };
@@ -352,3 +388,10 @@ pub async fn handle_websock_message(
         }
     }
 }
+
+/// Tells the client its request failed, so it does not wait for a response.
+async fn report_error(user_serv_snd: &MAsyncTx<ServiceMessage>, err: &ServiceError) {
+    if let Err(send_err) = user_serv_snd.send(ServiceMessage::ErrorRsp(err.into())).await {
+        error!("Failed to send ErrorResponse: {}", send_err);
+    }
+}
@@ -0,0 +1,203 @@
+use std::sync::Arc;
+
+use tokio::time::Instant;
+use tonic::{
+    Request, Status,
+    metadata::{MetadataMap, MetadataValue},
+    service::Interceptor,
+};
+use tracing::warn;
+
+/// Only sent for users who proved their identity with a ticket.
+const USER_ID_KEY: &str = "x-user-id";
+/// The user ID a client connected under without any proof of it.
+const CLAIMED_USER_ID_KEY: &str = "x-claimed-user-id";
+const SESSION_ID_KEY: &str = "x-session-id";
+const CONNECTOR_ID_KEY: &str = "x-connector-id";
+const REQUEST_ID_KEY: &str = "x-request-id";
+const TRACEPARENT_KEY: &str = "traceparent";
+
+/// The WebSocket connection of a user to this connector.
+#[derive(Debug)]
+pub struct Session {
+    user_id: String,
+    /// Whether the user ID was checked against a ticket rather than taken
+    /// from the connection path as is.
+    verified: bool,
+    session_id: String,
+    connector_id: String,
+}
+
+impl Session {
+    pub fn new(user_id: &str, connector_id: &str) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            verified: false,
+            session_id: random_hex_u128(),
+            connector_id: connector_id.to_string(),
+        }
+    }
+
+    /// Marks the user ID as verified, for upstreams to trust it.
+    pub fn with_verified_user(mut self) -> Self {
+        self.verified = true;
+
+        self
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Starts the context of a new client request, with its own request ID and
+    /// trace.
+    pub fn call_context(&self) -> CallContext {
+        CallContext {
+            inner: Arc::new(ContextInner {
+                user_id: self.user_id.clone(),
+                verified: self.verified,
+                session_id: self.session_id.clone(),
+                connector_id: self.connector_id.clone(),
+                request_id: random_hex_u128(),
+                trace_id: random_hex_u128(),
+            }),
+        }
+    }
+}
+
+/// Identity and trace context of a client request, sent along with every
+/// upstream call made to serve it.
+#[derive(Clone, Debug)]
+pub struct CallContext {
+    inner: Arc<ContextInner>,
+}
+
+#[derive(Debug)]
+struct ContextInner {
+    user_id: String,
+    verified: bool,
+    session_id: String,
+    connector_id: String,
+    request_id: String,
+    trace_id: String,
+}
+
+impl CallContext {
+    pub fn request_id(&self) -> &str {
+        &self.inner.request_id
+    }
+
+    /// An interceptor adding the context to the metadata of outbound calls.
+    pub fn interceptor(&self) -> CallInterceptor {
+        CallInterceptor {
+            context: self.clone(),
+            deadline: None,
+        }
+    }
+
+    fn apply(&self, metadata: &mut MetadataMap) {
+        // Every call is a child span of the request trace.
+        let traceparent = format!(
+            "00-{}-{:016x}-01",
+            self.inner.trace_id,
+            rand::random::<u64>() | 1
+        );
+
+        let user_id_key = match self.inner.verified {
+            true => USER_ID_KEY,
+            false => CLAIMED_USER_ID_KEY,
+        };
+
+        let entries = [
+            (user_id_key, self.inner.user_id.as_str()),
+            (SESSION_ID_KEY, self.inner.session_id.as_str()),
+            (CONNECTOR_ID_KEY, self.inner.connector_id.as_str()),
+            (REQUEST_ID_KEY, self.inner.request_id.as_str()),
+            (TRACEPARENT_KEY, traceparent.as_str()),
+        ];
+
+        for (key, value) in entries {
+            match MetadataValue::try_from(value) {
+                Ok(value) => {
+                    metadata.insert(key, value);
+                }
+                Err(err) => warn!("Skipping invalid {} metadata {:?}: {}", key, value, err),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CallInterceptor {
+    context: CallContext,
+    deadline: Option<Instant>,
+}
+
+impl CallInterceptor {
+    /// Sends the time left until `deadline` as the `grpc-timeout` of the call,
+    /// so that the upstream gives up when the connector does.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+
+        self
+    }
+}
+
+impl Interceptor for CallInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        self.context.apply(request.metadata_mut());
+
+        if let Some(deadline) = self.deadline {
+            request.set_timeout(deadline.saturating_duration_since(Instant::now()));
+        }
+
+        Ok(request)
+    }
+}
+
+fn random_hex_u128() -> String {
+    format!("{:032x}", rand::random::<u128>() | 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn intercept(mut interceptor: CallInterceptor) -> Request<()> {
+        interceptor.call(Request::new(())).unwrap()
+    }
+
+    #[test]
+    fn sends_the_context_metadata() {
+        let context = Session::new("user-1", "connector-1").call_context();
+
+        let request = intercept(context.interceptor());
+        let metadata = request.metadata();
+
+        assert_eq!(metadata.get(CLAIMED_USER_ID_KEY).unwrap(), "user-1");
+        assert!(metadata.get(USER_ID_KEY).is_none());
+        assert_eq!(metadata.get(CONNECTOR_ID_KEY).unwrap(), "connector-1");
+        assert_eq!(metadata.get(REQUEST_ID_KEY).unwrap(), context.request_id());
+        assert!(metadata.get("grpc-timeout").is_none());
+    }
+
+    #[test]
+    fn sends_the_time_left_as_grpc_timeout() {
+        let context = Session::new("user-1", "connector-1").call_context();
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        let request = intercept(context.interceptor().with_deadline(deadline));
+
+        let timeout = request
+            .metadata()
+            .get("grpc-timeout")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let micros: u64 = timeout.strip_suffix('u').unwrap().parse().unwrap();
+
+        assert!(micros > 4_000_000 && micros <= 5_000_000, "{}", timeout);
+    }
+}
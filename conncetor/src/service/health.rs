@@ -1,6 +1,59 @@
-use crate::service::result::{ServiceResult, succeed};
+use std::time::Duration;
 
-pub async fn health_check() -> ServiceResult<()> {
+use reqwest::StatusCode;
+
+use crate::{
+    service::result::{ServiceResult, succeed},
+    state::AppState,
+};
+
+pub async fn health_check(state: &AppState) -> ServiceResult<()> {
     // Add some dependency checks here if needed in the future.
+    if state.is_degraded() {
+        return Ok(
+            succeed().with_message("Degraded: upstreams are served from the registry snapshot.")
+        );
+    }
+
     Ok(succeed().with_message("Health response from server."))
 }
+
+const CACHE_PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Why the connector should not take traffic, empty when it is ready.
+pub async fn unready_reasons(state: &AppState) -> Vec<String> {
+    let mut reasons = Vec::new();
+
+    if state.is_draining() {
+        reasons.push("Draining".to_string());
+    }
+
+    let stale = state.stale_upstreams();
+
+    if !stale.is_empty() {
+        reasons.push(format!("Stale upstreams: {}", stale.join(", ")));
+    }
+
+    match tokio::time::timeout(CACHE_PING_TIMEOUT, state.cache().ping_remote()).await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => reasons.push(format!("Cache unreachable: {}", err)),
+        Err(_) => reasons.push("Cache ping timed out".to_string()),
+    }
+
+    reasons
+}
+
+/// Fails while draining, while the cache is unreachable or while the instances
+/// of any upstream are stale, so that traffic is steered away from a connector
+/// that cannot serve it.
+pub async fn readiness_check(state: &AppState) -> ServiceResult<()> {
+    let reasons = unready_reasons(state).await;
+
+    if !reasons.is_empty() {
+        return Ok(succeed()
+            .with_code(StatusCode::SERVICE_UNAVAILABLE)
+            .with_message(format!("{}.", reasons.join("; "))));
+    }
+
+    Ok(succeed().with_message("Ready."))
+}
@@ -4,10 +4,12 @@ use crate::{
     model::dto::{
         CreateMessageReq, CreateMessageRsp, ListMessagesReq, ListMessagesRsp, MessageDetail,
     },
-    registry::{ConsulRegistry, store::Store},
+    registry::ServiceRegistry,
     service::{
-        ServiceError, ServiceResult,
-        message::message_service::message_service_client::MessageServiceClient, succeed,
+        CallContext, ServiceResult,
+        message::message_service::message_service_client::MessageServiceClient,
+        succeed,
+        upstream::{self, RouteKeys, UpstreamRpc},
     },
 };
 
@@ -17,26 +19,33 @@ mod message_service {
 
 pub async fn create_message(
     args: CreateMessageReq,
-    registry: &ConsulRegistry<Channel>,
+    registry: &ServiceRegistry<Channel>,
+    ctx: &CallContext,
 ) -> ServiceResult<CreateMessageRsp> {
-    let chan = registry
-        .store()
-        .read()
-        .unwrap()
-        .pick(&args.user_id)
-        .ok_or_else(|| ServiceError::UpstreamUnaccesibleError)?
-        .extra_data()
-        .clone();
-
-    let mut client: MessageServiceClient<Channel> = MessageServiceClient::new(chan);
-
-    let grpc_request = tonic::Request::new(message_service::CreateMessageRequest {
-        user_id: args.user_id,
-        channel_id: args.channel_id,
+    let grpc_request = message_service::CreateMessageRequest {
+        user_id: args.user_id.clone(),
+        channel_id: args.channel_id.clone(),
         content: args.content,
-    });
+    };
+
+    let grpc_response = upstream::call(
+        registry,
+        UpstreamRpc::CreateMessage,
+        RouteKeys::new()
+            .with_user(&args.user_id)
+            .with_channel(&args.channel_id),
+        ctx,
+        |chan| {
+            let grpc_request = grpc_request.clone();
 
-    let grpc_response = client.create_message(grpc_request).await?.into_inner();
+            async move {
+                MessageServiceClient::new(chan)
+                    .create_message(grpc_request)
+                    .await
+            }
+        },
+    )
+    .await?;
 
     Ok(succeed().with_data(CreateMessageRsp {
         message_id: grpc_response.message_id,
@@ -45,29 +54,31 @@ pub async fn create_message(
 
 pub async fn list_channel_messages(
     args: ListMessagesReq,
-    registry: &ConsulRegistry<Channel>,
+    registry: &ServiceRegistry<Channel>,
+    ctx: &CallContext,
 ) -> ServiceResult<ListMessagesRsp> {
-    let chan = registry
-        .store()
-        .read()
-        .unwrap()
-        .pick(&args.channel_id)
-        .ok_or_else(|| ServiceError::UpstreamUnaccesibleError)?
-        .extra_data()
-        .clone();
-
-    let mut client = MessageServiceClient::new(chan);
-
-    let grpc_request = tonic::Request::new(message_service::ListChannelMessagesRequest {
-        channel_id: args.channel_id,
+    let grpc_request = message_service::ListChannelMessagesRequest {
+        channel_id: args.channel_id.clone(),
         limit: args.limit as i32,
         latest_time: args.latest_time,
-    });
+    };
+
+    let grpc_response = upstream::call(
+        registry,
+        UpstreamRpc::ListChannelMessages,
+        RouteKeys::new().with_channel(&args.channel_id),
+        ctx,
+        |chan| {
+            let grpc_request = grpc_request.clone();
 
-    let grpc_response = client
-        .list_channel_messages(grpc_request)
-        .await?
-        .into_inner();
+            async move {
+                MessageServiceClient::new(chan)
+                    .list_channel_messages(grpc_request)
+                    .await
+            }
+        },
+    )
+    .await?;
 
     let messages: Vec<MessageDetail> = grpc_response
         .messages
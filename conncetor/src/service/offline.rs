@@ -0,0 +1,109 @@
+use crossfire::AsyncRx;
+use redis::RedisResult;
+
+use crate::{cache::CacheClient, config::AppConfig, message::ServiceMessage};
+
+const OFFLINE_KEY_PREFIX: &str = "offline:";
+
+/// Stores a serialized message for users who are not connected, to be sent
+/// once they connect again. Only the latest `offline_queue_len` messages of a
+/// user are kept.
+pub async fn queue_offline(
+    cache: &CacheClient,
+    config: &AppConfig,
+    user_ids: &[String],
+    payload: &str,
+) -> RedisResult<()> {
+    let keys: Vec<String> = user_ids
+        .iter()
+        .map(|user_id| offline_key(user_id))
+        .collect();
+
+    cache
+        .list_push_capped(
+            &keys,
+            payload,
+            config.offline_queue_len(),
+            config.offline_queue_ttl_secs() as i64,
+        )
+        .await
+}
+
+/// Removes and returns the messages stored for a user, oldest first.
+pub async fn take_offline(cache: &CacheClient, user_id: &str) -> RedisResult<Vec<String>> {
+    cache.list_take(&offline_key(user_id)).await
+}
+
+fn offline_key(user_id: &str) -> String {
+    format!("{}{}", OFFLINE_KEY_PREFIX, user_id)
+}
+
+/// The messages to send on a new connection: those stored while the user was
+/// offline first, then the live ones, which wait in their queue meanwhile.
+pub struct Outbox {
+    pending: std::vec::IntoIter<String>,
+    live: AsyncRx<ServiceMessage>,
+}
+
+impl Outbox {
+    pub fn new(pending: Vec<String>, live: AsyncRx<ServiceMessage>) -> Self {
+        Self {
+            pending: pending.into_iter(),
+            live,
+        }
+    }
+
+    /// Returns `None` once the live queue is closed.
+    pub async fn recv(&mut self) -> Option<ServiceMessage> {
+        match self.pending.next() {
+            Some(payload) => Some(ServiceMessage::Serialized(payload.into())),
+            None => self.live.recv().await.ok(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossfire::mpsc;
+
+    use super::*;
+
+    fn payload(message: Option<ServiceMessage>) -> String {
+        match message {
+            Some(ServiceMessage::Serialized(payload)) => payload.to_string(),
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_offline_messages_before_live_ones() {
+        let (tx, rx) = mpsc::bounded_async(4);
+
+        // Dispatched while the offline messages were being fetched.
+        tx.send(ServiceMessage::Serialized("live".into()))
+            .await
+            .unwrap();
+
+        let mut outbox = Outbox::new(vec!["first".to_string(), "second".to_string()], rx);
+
+        assert_eq!(payload(outbox.recv().await), "first");
+        assert_eq!(payload(outbox.recv().await), "second");
+        assert_eq!(payload(outbox.recv().await), "live");
+
+        drop(tx);
+
+        assert!(outbox.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn sends_pending_messages_after_the_live_queue_closed() {
+        let (tx, rx) = mpsc::bounded_async(4);
+
+        drop(tx);
+
+        let mut outbox = Outbox::new(vec!["first".to_string()], rx);
+
+        assert_eq!(payload(outbox.recv().await), "first");
+        assert!(outbox.recv().await.is_none());
+    }
+}
@@ -1,6 +1,11 @@
+use std::time::Duration;
+
 use reqwest::StatusCode;
 use serde::Serialize;
 use thiserror::Error;
+use tonic::Code;
+
+use crate::model::dto::ErrorRsp;
 
 pub type ServiceResult<T> = Result<ServiceValue<T>, ServiceError>;
 
@@ -19,6 +24,58 @@ pub enum ServiceError {
     GprcStatusError(#[from] tonic::Status),
     #[error("Upstream unaccesible error")]
     UpstreamUnaccesibleError,
+    #[error("Upstream timed out after {0:?}")]
+    UpstreamTimeoutError(Duration),
+    #[error("No connector available")]
+    NoConnectorAvailable,
+    #[error("Caller not authenticated")]
+    Unauthenticated,
+}
+
+impl ServiceError {
+    /// Machine readable kind of the error reported to clients.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::UpstreamTimeoutError(_) => "upstream_timeout",
+            Self::GprcStatusError(status) if status.code() == Code::DeadlineExceeded => {
+                "upstream_timeout"
+            }
+            Self::UpstreamUnaccesibleError => "upstream_unavailable",
+            Self::GprcStatusError(status) if status.code() == Code::Unavailable => {
+                "upstream_unavailable"
+            }
+            Self::NoConnectorAvailable => "no_connector_available",
+            Self::Unauthenticated => "unauthenticated",
+            Self::TonicTransportError(_) | Self::GprcStatusError(_) => "upstream_error",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UpstreamTimeoutError(_) => StatusCode::GATEWAY_TIMEOUT,
+            Self::GprcStatusError(status) if status.code() == Code::DeadlineExceeded => {
+                StatusCode::GATEWAY_TIMEOUT
+            }
+            Self::UpstreamUnaccesibleError | Self::NoConnectorAvailable => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::GprcStatusError(status) if status.code() == Code::Unavailable => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Self::TonicTransportError(_) | Self::GprcStatusError(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+impl From<&ServiceError> for ErrorRsp {
+    fn from(err: &ServiceError) -> Self {
+        Self {
+            code: err.status_code().as_u16(),
+            kind: err.kind().to_string(),
+            message: err.to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
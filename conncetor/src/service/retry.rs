@@ -0,0 +1,278 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Deserializer, de::Error as _};
+use tonic::Code;
+
+/// How an upstream retries idempotent calls failing with a retryable status.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RetryPolicy {
+    #[serde(default = "RetryPolicy::default_max_retries")]
+    max_retries: u32,
+    #[serde(default = "RetryPolicy::default_base_backoff_ms")]
+    base_backoff_ms: u64,
+    #[serde(default = "RetryPolicy::default_max_backoff_ms")]
+    max_backoff_ms: u64,
+    /// gRPC status codes worth retrying, in snake case, e.g. `unavailable`.
+    /// A timed out attempt counts as `deadline_exceeded`.
+    #[serde(
+        default = "RetryPolicy::default_retryable_codes",
+        deserialize_with = "deserialize_codes"
+    )]
+    retryable_codes: Vec<Code>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: Self::default_max_retries(),
+            base_backoff_ms: Self::default_base_backoff_ms(),
+            max_backoff_ms: Self::default_max_backoff_ms(),
+            retryable_codes: Self::default_retryable_codes(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn default_max_retries() -> u32 {
+        2
+    }
+
+    fn default_base_backoff_ms() -> u64 {
+        50
+    }
+
+    fn default_max_backoff_ms() -> u64 {
+        1_000
+    }
+
+    fn default_retryable_codes() -> Vec<Code> {
+        vec![Code::Unavailable, Code::ResourceExhausted]
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    pub fn is_retryable(&self, code: Code) -> bool {
+        self.retryable_codes.contains(&code)
+    }
+
+    /// Exponential backoff with full jitter before the `retry`-th retry,
+    /// counting from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .base_backoff_ms
+            .saturating_mul(1 << retry.saturating_sub(1).min(16))
+            .min(self.max_backoff_ms);
+
+        Duration::from_millis(rand::random_range(0..=ceiling))
+    }
+}
+
+fn deserialize_codes<'de, D>(deserializer: D) -> Result<Vec<Code>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|name| {
+            let code = match name.as_str() {
+                "cancelled" => Code::Cancelled,
+                "unknown" => Code::Unknown,
+                "invalid_argument" => Code::InvalidArgument,
+                "deadline_exceeded" => Code::DeadlineExceeded,
+                "not_found" => Code::NotFound,
+                "already_exists" => Code::AlreadyExists,
+                "permission_denied" => Code::PermissionDenied,
+                "resource_exhausted" => Code::ResourceExhausted,
+                "failed_precondition" => Code::FailedPrecondition,
+                "aborted" => Code::Aborted,
+                "out_of_range" => Code::OutOfRange,
+                "unimplemented" => Code::Unimplemented,
+                "internal" => Code::Internal,
+                "unavailable" => Code::Unavailable,
+                "data_loss" => Code::DataLoss,
+                "unauthenticated" => Code::Unauthenticated,
+                _ => return Err(D::Error::custom(format!("unknown gRPC code {}", name))),
+            };
+
+            Ok(code)
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RetryBudgetConfig {
+    /// Retries allowed per call made, e.g. 0.2 for one retry every 5 calls.
+    #[serde(default = "RetryBudgetConfig::default_ratio")]
+    ratio: f64,
+    /// Retries allowed per second regardless of the traffic.
+    #[serde(default = "RetryBudgetConfig::default_min_per_sec")]
+    min_per_sec: f64,
+    /// Retries that can be saved up during calm periods.
+    #[serde(default = "RetryBudgetConfig::default_burst")]
+    burst: f64,
+}
+
+impl Default for RetryBudgetConfig {
+    fn default() -> Self {
+        Self {
+            ratio: Self::default_ratio(),
+            min_per_sec: Self::default_min_per_sec(),
+            burst: Self::default_burst(),
+        }
+    }
+}
+
+impl RetryBudgetConfig {
+    fn default_ratio() -> f64 {
+        0.2
+    }
+
+    fn default_min_per_sec() -> f64 {
+        10.0
+    }
+
+    fn default_burst() -> f64 {
+        100.0
+    }
+}
+
+/// A token bucket shared by all upstream calls, so that an outage turning
+/// every call into retries cannot multiply the load on the upstreams.
+///
+/// Every call deposits `ratio` tokens, time deposits `min_per_sec` tokens per
+/// second, and every retry takes one token.
+#[derive(Debug)]
+pub struct RetryBudget {
+    config: RetryBudgetConfig,
+    state: Mutex<BudgetState>,
+}
+
+#[derive(Debug)]
+struct BudgetState {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        Self::new(RetryBudgetConfig::default())
+    }
+}
+
+impl RetryBudget {
+    pub fn new(config: RetryBudgetConfig) -> Self {
+        Self {
+            state: Mutex::new(BudgetState {
+                tokens: config.burst,
+                refilled_at: Instant::now(),
+            }),
+            config,
+        }
+    }
+
+    pub fn deposit(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        state.tokens = (state.tokens + self.config.ratio).min(self.config.burst);
+    }
+
+    pub fn try_withdraw(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.refilled_at).as_secs_f64();
+
+        state.tokens = (state.tokens + elapsed * self.config.min_per_sec).min(self.config.burst);
+        state.refilled_at = now;
+
+        if state.tokens < 1.0 {
+            return false;
+        }
+
+        state.tokens -= 1.0;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(ratio: f64, min_per_sec: f64, burst: f64) -> RetryBudget {
+        RetryBudget::new(RetryBudgetConfig {
+            ratio,
+            min_per_sec,
+            burst,
+        })
+    }
+
+    #[test]
+    fn backoff_stays_below_its_exponential_ceiling() {
+        let policy = RetryPolicy {
+            base_backoff_ms: 50,
+            max_backoff_ms: 1_000,
+            ..RetryPolicy::default()
+        };
+
+        for (retry, ceiling_ms) in [(0, 50), (1, 50), (2, 100), (3, 200), (5, 800), (6, 1_000)] {
+            for _ in 0..100 {
+                assert!(policy.backoff(retry) <= Duration::from_millis(ceiling_ms));
+            }
+        }
+
+        // The shift is capped, so a huge retry count neither overflows nor
+        // goes past the maximum.
+        assert!(policy.backoff(u32::MAX) <= Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn budget_runs_out_after_its_burst() {
+        let budget = budget(0.2, 0.0, 3.0);
+
+        assert!((0..3).all(|_| budget.try_withdraw()));
+        assert!(!budget.try_withdraw());
+    }
+
+    #[test]
+    fn calls_refill_the_budget() {
+        let budget = budget(0.5, 0.0, 1.0);
+
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+
+        budget.deposit();
+        assert!(!budget.try_withdraw());
+
+        budget.deposit();
+        assert!(budget.try_withdraw());
+    }
+
+    #[test]
+    fn deposits_are_capped_by_the_burst() {
+        let budget = budget(1.0, 0.0, 2.0);
+
+        for _ in 0..10 {
+            budget.deposit();
+        }
+
+        assert!((0..2).all(|_| budget.try_withdraw()));
+        assert!(!budget.try_withdraw());
+    }
+
+    #[test]
+    fn time_refills_the_budget() {
+        let budget = budget(0.0, 1_000.0, 1.0);
+
+        assert!(budget.try_withdraw());
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(budget.try_withdraw());
+    }
+}
@@ -0,0 +1,145 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A ticket binds a user to the connector it was assigned to until it expires.
+/// It is `base64(payload).base64(hmac)`, the payload being the user ID, the
+/// connector ID and the expiry as Unix seconds, separated by newlines.
+#[derive(Debug, Error)]
+pub enum TicketError {
+    #[error("Malformed ticket")]
+    Malformed,
+    #[error("Invalid ticket signature")]
+    InvalidSignature,
+    #[error("Ticket expired")]
+    Expired,
+    #[error("Ticket issued for another user or connector")]
+    Mismatch,
+}
+
+/// Returns the ticket and its expiry as Unix seconds.
+pub fn issue_ticket(
+    secret: &str,
+    user_id: &str,
+    connector_id: &str,
+    ttl: Duration,
+) -> (String, i64) {
+    let expires_at = unix_now() + ttl.as_secs() as i64;
+
+    let payload = format!("{}\n{}\n{}", user_id, connector_id, expires_at);
+
+    let signature = mac(secret, payload.as_bytes()).finalize().into_bytes();
+
+    let ticket = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(payload),
+        URL_SAFE_NO_PAD.encode(signature)
+    );
+
+    (ticket, expires_at)
+}
+
+pub fn verify_ticket(
+    secret: &str,
+    ticket: &str,
+    user_id: &str,
+    connector_id: &str,
+) -> Result<(), TicketError> {
+    let (payload, signature) = ticket.split_once('.').ok_or(TicketError::Malformed)?;
+
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|_| TicketError::Malformed)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| TicketError::Malformed)?;
+
+    // Constant time comparison, before looking at anything in the payload.
+    mac(secret, &payload)
+        .verify_slice(&signature)
+        .map_err(|_| TicketError::InvalidSignature)?;
+
+    let payload = String::from_utf8(payload).map_err(|_| TicketError::Malformed)?;
+
+    let mut fields = payload.split('\n');
+
+    let (Some(ticket_user_id), Some(ticket_connector_id), Some(expires_at), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return Err(TicketError::Malformed);
+    };
+
+    let expires_at: i64 = expires_at.parse().map_err(|_| TicketError::Malformed)?;
+
+    if expires_at < unix_now() {
+        return Err(TicketError::Expired);
+    }
+
+    if ticket_user_id != user_id || ticket_connector_id != connector_id {
+        return Err(TicketError::Mismatch);
+    }
+
+    Ok(())
+}
+
+/// Whether `authorization` is `Bearer {token}`, compared in constant time.
+pub fn verify_bearer(token: &str, authorization: Option<&str>) -> bool {
+    let Some(presented) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+        return false;
+    };
+
+    // Comparing MACs of both keeps the comparison constant time.
+    mac(token, presented.as_bytes())
+        .verify_slice(&mac(token, token.as_bytes()).finalize().into_bytes())
+        .is_ok()
+}
+
+fn mac(secret: &str, payload: &[u8]) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+
+    mac.update(payload);
+
+    mac
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "ticket-secret";
+
+    #[test]
+    fn ticket_is_bound_to_user_and_connector() {
+        let (ticket, _) = issue_ticket(SECRET, "alice", "connector-1", Duration::from_secs(30));
+
+        assert!(verify_ticket(SECRET, &ticket, "alice", "connector-1").is_ok());
+        assert!(matches!(
+            verify_ticket(SECRET, &ticket, "bob", "connector-1"),
+            Err(TicketError::Mismatch)
+        ));
+        assert!(matches!(
+            verify_ticket("other-secret", &ticket, "alice", "connector-1"),
+            Err(TicketError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn bearer_must_match_token() {
+        assert!(verify_bearer("token", Some("Bearer token")));
+        assert!(!verify_bearer("token", Some("Bearer other")));
+        assert!(!verify_bearer("token", Some("token")));
+        assert!(!verify_bearer("token", None));
+    }
+}
@@ -0,0 +1,297 @@
+use serde::Deserialize;
+use tokio::time::Instant;
+use tonic::{Code, Response, Status, service::interceptor::InterceptedService, transport::Channel};
+use tracing::{debug, warn};
+
+use crate::{
+    registry::{ServiceRegistry, store::Store},
+    service::{CallContext, CallInterceptor, ServiceError},
+};
+
+/// A channel to an upstream instance, adding the call context to every request.
+pub type UpstreamChannel = InterceptedService<Channel, CallInterceptor>;
+
+/// The upstream RPCs the connector calls on behalf of its clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamRpc {
+    RegisterUser,
+    LoginUser,
+    GetUserInfo,
+    CreateChannel,
+    JoinChannel,
+    ListChannelDetails,
+    CreateMessage,
+    ListChannelMessages,
+}
+
+impl UpstreamRpc {
+    /// Whether sending the RPC twice has the same effect as sending it once,
+    /// which makes it safe to send again to another instance.
+    ///
+    /// Logging in is left out, as the upstream may record the login or issue a
+    /// session for every attempt.
+    pub fn is_idempotent(&self) -> bool {
+        match self {
+            Self::GetUserInfo | Self::ListChannelDetails | Self::ListChannelMessages => true,
+            Self::RegisterUser
+            | Self::LoginUser
+            | Self::CreateChannel
+            | Self::JoinChannel
+            | Self::CreateMessage => false,
+        }
+    }
+
+    /// The routing key used unless the upstream configuration overrides it.
+    ///
+    /// Everything about a channel routes by the channel, so that its writes and
+    /// reads land on the same instance.
+    pub fn default_routing_key(&self) -> RoutingKey {
+        match self {
+            Self::RegisterUser
+            | Self::LoginUser
+            | Self::GetUserInfo
+            | Self::CreateChannel
+            | Self::ListChannelDetails => RoutingKey::User,
+            Self::JoinChannel | Self::CreateMessage | Self::ListChannelMessages => {
+                RoutingKey::Channel
+            }
+        }
+    }
+}
+
+/// Which part of a request picks the upstream instance serving it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingKey {
+    User,
+    Channel,
+    /// Spreads requests without any affinity.
+    Random,
+}
+
+/// The keys a request can be routed by.
+///
+/// When the configured key is missing from a request, e.g. routing
+/// `CreateChannel` by channel before the channel exists, the other one is used.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RouteKeys<'a> {
+    user: Option<&'a str>,
+    channel: Option<&'a str>,
+}
+
+impl<'a> RouteKeys<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_user(mut self, user: &'a str) -> Self {
+        self.user = Some(user);
+
+        self
+    }
+
+    pub fn with_channel(mut self, channel: &'a str) -> Self {
+        self.channel = Some(channel);
+
+        self
+    }
+
+    fn resolve(&self, routing_key: RoutingKey) -> String {
+        let key = match routing_key {
+            RoutingKey::User => self.user.or(self.channel),
+            RoutingKey::Channel => self.channel.or(self.user),
+            RoutingKey::Random => None,
+        };
+
+        match key {
+            Some(key) => key.to_string(),
+            None => rand::random::<u64>().to_string(),
+        }
+    }
+}
+
+/// Sends `rpc` to the instance of `registry` owning the routing key of the
+/// request, picked from `keys` as configured for the upstream.
+///
+/// The timeout configured for `rpc` bounds the whole call, failovers and
+/// retries included, and is sent along as the gRPC deadline of every attempt.
+/// Attempts skip the instances whose circuit breaker rejects calls. Idempotent
+/// RPCs are sent again when they fail:
+///
+/// - If the instance is unreachable or times out, right away to the next
+///   candidate of the store, up to the `max_failovers` of the upstream.
+/// - Otherwise, if the status is retryable for the upstream and the global
+///   retry budget allows it, after a backoff to the next candidate in turn.
+pub async fn call<R, F, Fut>(
+    registry: &ServiceRegistry<Channel>,
+    rpc: UpstreamRpc,
+    keys: RouteKeys<'_>,
+    ctx: &CallContext,
+    mut send: F,
+) -> Result<R, ServiceError>
+where
+    F: FnMut(UpstreamChannel) -> Fut,
+    Fut: Future<Output = Result<Response<R>, Status>>,
+{
+    let attempts = match rpc.is_idempotent() {
+        true => 1 + registry.upstream().max_failovers(),
+        false => 1,
+    };
+
+    let routing_key = registry.upstream().routing_key(rpc);
+    let key = keys.resolve(routing_key);
+
+    debug!(
+        "Routing {:?} of request {} by {:?} key {}",
+        rpc,
+        ctx.request_id(),
+        routing_key,
+        key
+    );
+
+    let candidates = registry.store().candidates(&key, attempts);
+
+    if candidates.is_empty() {
+        return Err(ServiceError::UpstreamUnaccesibleError);
+    }
+
+    let timeout = registry.upstream().timeout(rpc);
+    let retry = registry.upstream().retry();
+    let breaker = registry.upstream().breaker();
+
+    registry.retry_budget().deposit();
+
+    let deadline = Instant::now() + timeout;
+
+    let mut index = 0;
+    let mut retries = 0;
+    let mut last_error = None;
+
+    loop {
+        if Instant::now() >= deadline {
+            return Err(last_error.unwrap_or(ServiceError::UpstreamTimeoutError(timeout)));
+        }
+
+        let instance = &candidates[index];
+        let instance_id = instance.entry().info().id();
+
+        if !instance.breaker().try_acquire(breaker) {
+            debug!(
+                "Circuit breaker of instance {} rejected {:?}",
+                instance_id, rpc
+            );
+
+            if index + 1 < candidates.len() {
+                index += 1;
+
+                continue;
+            }
+
+            return Err(last_error.unwrap_or(ServiceError::UpstreamUnaccesibleError));
+        }
+
+        let (code, err) = {
+            let _in_flight = instance.track();
+
+            let channel = InterceptedService::new(
+                instance.extra_data().clone(),
+                ctx.interceptor().with_deadline(deadline),
+            );
+
+            match tokio::time::timeout_at(deadline, send(channel)).await {
+                Ok(Ok(response)) => {
+                    instance.breaker().record_success();
+
+                    return Ok(response.into_inner());
+                }
+                Ok(Err(status)) => (status.code(), ServiceError::from(status)),
+                Err(_) => (
+                    Code::DeadlineExceeded,
+                    ServiceError::UpstreamTimeoutError(timeout),
+                ),
+            }
+        };
+
+        if is_instance_failure(code) {
+            if instance.breaker().record_failure(breaker) {
+                warn!(
+                    "Circuit breaker of {} instance {} opened: {}",
+                    instance.entry().info().name(),
+                    instance_id,
+                    err
+                );
+            }
+        } else {
+            // The instance answered, the request itself was at fault.
+            instance.breaker().record_success();
+        }
+
+        if !rpc.is_idempotent() {
+            return Err(err);
+        }
+
+        if matches!(code, Code::Unavailable | Code::DeadlineExceeded)
+            && index + 1 < candidates.len()
+        {
+            warn!(
+                "{:?} failed on instance {}, failing over: {}",
+                rpc, instance_id, err
+            );
+
+            last_error = Some(err);
+            index += 1;
+
+            continue;
+        }
+
+        if !retry.is_retryable(code) || retries >= retry.max_retries() {
+            return Err(err);
+        }
+
+        if !registry.retry_budget().try_withdraw() {
+            warn!(
+                "Retry budget exhausted, not retrying {:?} on instance {}: {}",
+                rpc, instance_id, err
+            );
+
+            return Err(err);
+        }
+
+        retries += 1;
+
+        let backoff = retry.backoff(retries);
+
+        if Instant::now() + backoff >= deadline {
+            warn!(
+                "No time left to retry {:?} on instance {}: {}",
+                rpc, instance_id, err
+            );
+
+            return Err(err);
+        }
+
+        warn!(
+            "{:?} failed on instance {}, retry {} in {:?}: {}",
+            rpc, instance_id, retries, backoff, err
+        );
+
+        tokio::time::sleep(backoff).await;
+
+        last_error = Some(err);
+        index = (index + 1) % candidates.len();
+    }
+}
+
+/// Whether a call failing with `code` says something about the health of the
+/// instance, rather than about the request.
+fn is_instance_failure(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unavailable
+            | Code::DeadlineExceeded
+            | Code::ResourceExhausted
+            | Code::Internal
+            | Code::Unknown
+    )
+}
@@ -5,36 +5,40 @@ mod user_service {
 use crate::model::dto::{
     GetUserInfoReq, GetUserInfoRsp, LoginUserReq, LoginUserRsp, RegisterUserReq, RegisterUserRsp,
 };
-use crate::registry::store::Store;
 // TODO: Do not create a new client for each request. Implement connection pooling.
+use crate::service::CallContext;
+use crate::service::succeed;
+use crate::service::upstream::{self, RouteKeys, UpstreamRpc};
 use crate::service::user::user_service::user_service_client::UserServiceClient;
-use crate::service::{ServiceError, succeed};
-use crate::{registry::ConsulRegistry, service::ServiceResult};
+use crate::{registry::ServiceRegistry, service::ServiceResult};
 use tonic::transport::Channel;
 
 pub async fn register_user(
     args: RegisterUserReq,
-    registry: &ConsulRegistry<Channel>,
+    registry: &ServiceRegistry<Channel>,
+    ctx: &CallContext,
 ) -> ServiceResult<RegisterUserRsp> {
-    let chan = registry
-        .store()
-        .read()
-        .unwrap()
-        .pick(&args.username)
-        .ok_or_else(|| ServiceError::UpstreamUnaccesibleError)?
-        .extra_data()
-        .clone();
-
-    let mut client = UserServiceClient::new(chan);
-
-    let grpc_request = tonic::Request::new(user_service::RegisterUserRequest {
-        nickname: args.username,
+    let grpc_request = user_service::RegisterUserRequest {
+        nickname: args.username.clone(),
         password: args.password,
-    });
-
-    let response = client.register_user(grpc_request).await?;
-
-    let grpc_response = response.into_inner();
+    };
+
+    let grpc_response = upstream::call(
+        registry,
+        UpstreamRpc::RegisterUser,
+        RouteKeys::new().with_user(&args.username),
+        ctx,
+        |chan| {
+            let grpc_request = grpc_request.clone();
+
+            async move {
+                UserServiceClient::new(chan)
+                    .register_user(grpc_request)
+                    .await
+            }
+        },
+    )
+    .await?;
 
     Ok(succeed().with_data(RegisterUserRsp {
         user_id: grpc_response.user_id,
@@ -43,27 +47,26 @@ pub async fn register_user(
 
 pub async fn login_user(
     args: LoginUserReq,
-    registry: &ConsulRegistry<Channel>,
+    registry: &ServiceRegistry<Channel>,
+    ctx: &CallContext,
 ) -> ServiceResult<LoginUserRsp> {
-    let chan = registry
-        .store()
-        .read()
-        .unwrap()
-        .pick(&args.username)
-        .ok_or_else(|| ServiceError::UpstreamUnaccesibleError)?
-        .extra_data()
-        .clone();
-
-    let mut client = UserServiceClient::new(chan);
-
-    let grpc_request = tonic::Request::new(user_service::LoginUserRequest {
-        nickname: args.username,
+    let grpc_request = user_service::LoginUserRequest {
+        nickname: args.username.clone(),
         password: args.password,
-    });
+    };
 
-    let response = client.login_user(grpc_request).await?;
+    let grpc_response = upstream::call(
+        registry,
+        UpstreamRpc::LoginUser,
+        RouteKeys::new().with_user(&args.username),
+        ctx,
+        |chan| {
+            let grpc_request = grpc_request.clone();
 
-    let grpc_response = response.into_inner();
+            async move { UserServiceClient::new(chan).login_user(grpc_request).await }
+        },
+    )
+    .await?;
 
     Ok(succeed().with_data(LoginUserRsp {
         token: grpc_response.token,
@@ -72,26 +75,29 @@ pub async fn login_user(
 
 pub async fn get_user_info(
     args: GetUserInfoReq,
-    registry: &ConsulRegistry<Channel>,
+    registry: &ServiceRegistry<Channel>,
+    ctx: &CallContext,
 ) -> ServiceResult<GetUserInfoRsp> {
-    let chan = registry
-        .store()
-        .read()
-        .unwrap()
-        .pick(&args.user_id)
-        .ok_or_else(|| ServiceError::UpstreamUnaccesibleError)?
-        .extra_data()
-        .clone();
-
-    let mut client = UserServiceClient::new(chan);
-
-    let grpc_request = tonic::Request::new(user_service::GetUserInfoRequest {
-        user_id: args.user_id,
-    });
-
-    let response = client.get_user_info(grpc_request).await?;
-
-    let grpc_response = response.into_inner();
+    let grpc_request = user_service::GetUserInfoRequest {
+        user_id: args.user_id.clone(),
+    };
+
+    let grpc_response = upstream::call(
+        registry,
+        UpstreamRpc::GetUserInfo,
+        RouteKeys::new().with_user(&args.user_id),
+        ctx,
+        |chan| {
+            let grpc_request = grpc_request.clone();
+
+            async move {
+                UserServiceClient::new(chan)
+                    .get_user_info(grpc_request)
+                    .await
+            }
+        },
+    )
+    .await?;
 
     Ok(succeed().with_data(GetUserInfoRsp {
         user_id: grpc_response.user_id,
@@ -2,12 +2,14 @@ use std::sync::Arc;
 
 use crossfire::MAsyncTx;
 use dashmap::DashMap;
+use tokio::sync::watch;
 use tonic::transport::Channel;
 
 use crate::cache::CacheClient;
 use crate::config::AppConfig;
 use crate::message::ServiceMessage;
-use crate::registry::ConsulRegistry;
+use crate::registry::ServiceRegistry;
+use crate::registry::discovery::Discovery;
 
 /// `AppState` is a cloneable wrapper around `AppStateInner` using `Arc`.
 #[derive(Clone, Debug)]
@@ -19,18 +21,23 @@ impl AppState {
     pub fn new(
         config: AppConfig,
         cache: CacheClient,
-        user_registry: ConsulRegistry<Channel>,
-        channel_registry: ConsulRegistry<Channel>,
-        message_registry: ConsulRegistry<Channel>,
+        discovery: Arc<dyn Discovery>,
+        user_registry: ServiceRegistry<Channel>,
+        channel_registry: ServiceRegistry<Channel>,
+        message_registry: ServiceRegistry<Channel>,
+        connector_registry: ServiceRegistry<()>,
     ) -> Self {
         Self {
             inner: Arc::new(Inner {
                 config,
                 cache,
-                user_registry: user_registry,
+                discovery,
+                user_registry,
                 channel_registry,
                 message_registry,
+                connector_registry,
                 online_users: DashMap::new(),
+                draining: watch::Sender::new(false),
             }),
         }
     }
@@ -43,21 +50,69 @@ impl AppState {
         &self.inner.cache
     }
 
-    pub fn user_registry(&self) -> &ConsulRegistry<Channel> {
+    pub fn discovery(&self) -> &Arc<dyn Discovery> {
+        &self.inner.discovery
+    }
+
+    pub fn user_registry(&self) -> &ServiceRegistry<Channel> {
         &self.inner.user_registry
     }
 
-    pub fn channel_registry(&self) -> &ConsulRegistry<Channel> {
+    pub fn channel_registry(&self) -> &ServiceRegistry<Channel> {
         &self.inner.channel_registry
     }
 
-    pub fn message_registry(&self) -> &ConsulRegistry<Channel> {
+    pub fn message_registry(&self) -> &ServiceRegistry<Channel> {
         &self.inner.message_registry
     }
 
+    /// The WebSocket endpoints of all connectors, this one included.
+    pub fn connector_registry(&self) -> &ServiceRegistry<()> {
+        &self.inner.connector_registry
+    }
+
+    /// Whether any upstream is still served from its registry snapshot.
+    pub fn is_degraded(&self) -> bool {
+        self.user_registry().is_degraded()
+            || self.channel_registry().is_degraded()
+            || self.message_registry().is_degraded()
+    }
+
+    pub fn upstream_registries(&self) -> [&ServiceRegistry<Channel>; 3] {
+        [
+            self.user_registry(),
+            self.channel_registry(),
+            self.message_registry(),
+        ]
+    }
+
+    /// Service names of the upstreams whose registry is stale.
+    pub fn stale_upstreams(&self) -> Vec<&str> {
+        self.upstream_registries()
+            .into_iter()
+            .filter(|registry| registry.is_stale())
+            .map(|registry| registry.service_prefix())
+            .collect()
+    }
+
     pub fn online_users(&self) -> &DashMap<String, MAsyncTx<ServiceMessage>> {
         &self.inner.online_users
     }
+
+    /// Reports the connector as not ready from now on, ahead of shutting down.
+    pub fn start_draining(&self) {
+        self.inner.draining.send_replace(true);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.inner.draining.borrow()
+    }
+
+    /// Notified when the connector starts draining, for health reports not to
+    /// wait for their next round.
+    pub fn subscribe_draining(&self) -> watch::Receiver<bool> {
+        self.inner.draining.subscribe()
+    }
 }
 
 /// `AppStateInner` has not to be Clone because `AppState` is the one being cloned.
@@ -65,8 +120,11 @@ impl AppState {
 struct Inner {
     config: AppConfig,
     cache: CacheClient,
-    user_registry: ConsulRegistry<Channel>,
-    channel_registry: ConsulRegistry<Channel>,
-    message_registry: ConsulRegistry<Channel>,
+    discovery: Arc<dyn Discovery>,
+    user_registry: ServiceRegistry<Channel>,
+    channel_registry: ServiceRegistry<Channel>,
+    message_registry: ServiceRegistry<Channel>,
+    connector_registry: ServiceRegistry<()>,
     online_users: DashMap<String, MAsyncTx<ServiceMessage>>,
+    draining: watch::Sender<bool>,
 }
@@ -0,0 +1,318 @@
+use std::{
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use arc_swap::ArcSwap;
+use hyper_util::rt::TokioIo;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{
+    TlsAcceptor, TlsConnector, client,
+    rustls::{
+        ClientConfig, RootCertStore, ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName, pem::PemObject},
+        server::WebPkiClientVerifier,
+    },
+    server,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{codegen::Service, transport::Uri};
+use tracing::{info, warn};
+
+use crate::config::{ClientTlsConfig, ServerTlsConfig};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TLS of the gRPC server, with certificates reloaded when their files change.
+///
+/// Only new connections pick up reloaded certificates.
+#[derive(Clone)]
+pub struct ServerTls {
+    config: Arc<ArcSwap<ServerConfig>>,
+}
+
+impl ServerTls {
+    pub fn new(config: &ServerTlsConfig, reload_interval: Duration) -> anyhow::Result<Self> {
+        if config.require_client_cert && config.client_ca.is_none() {
+            anyhow::bail!("Requiring client certificates needs a client CA");
+        }
+
+        let mut files = vec![PathBuf::from(&config.cert), PathBuf::from(&config.key)];
+        files.extend(config.client_ca.iter().map(PathBuf::from));
+
+        let tls_config = config.clone();
+        let config = spawn_reload("gRPC server", files, reload_interval, move || {
+            build_server_config(&tls_config)
+        })?;
+
+        Ok(Self { config })
+    }
+
+    /// Accepts connections from the listener and yields those completing the
+    /// TLS handshake.
+    pub fn incoming(
+        &self,
+        listener: TcpListener,
+    ) -> ReceiverStream<io::Result<server::TlsStream<TcpStream>>> {
+        let (tx, rx) = tokio::sync::mpsc::channel(128);
+        let config = self.config.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = tokio::select! {
+                    _ = tx.closed() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            warn!("Error accepting gRPC connection: {}", err);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    },
+                };
+
+                if let Err(err) = stream.set_nodelay(true) {
+                    warn!("Error setting TCP_NODELAY for {}: {}", peer, err);
+                }
+
+                // Handshakes run apart, so a slow client cannot hold up the others.
+                let acceptor = TlsAcceptor::from(config.load_full());
+                let tx = tx.clone();
+
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send(Ok(stream)).await;
+                        }
+                        Ok(Err(err)) => warn!("TLS handshake with {} failed: {}", peer, err),
+                        Err(_) => warn!("TLS handshake with {} timed out", peer),
+                    }
+                });
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+}
+
+/// TLS of the channels to upstream instances, with certificates reloaded when
+/// their files change.
+///
+/// Only new connections pick up reloaded certificates.
+#[derive(Clone)]
+pub struct ClientTls {
+    config: Arc<ArcSwap<ClientConfig>>,
+    server_name: Option<String>,
+}
+
+impl ClientTls {
+    pub fn new(config: &ClientTlsConfig, reload_interval: Duration) -> anyhow::Result<Self> {
+        if config.cert.is_some() != config.key.is_some() {
+            anyhow::bail!("A client certificate and its key must be set together");
+        }
+
+        let mut files = vec![PathBuf::from(&config.ca_cert)];
+        files.extend(config.cert.iter().map(PathBuf::from));
+        files.extend(config.key.iter().map(PathBuf::from));
+
+        let tls_config = config.clone();
+        let server_name = config.server_name.clone();
+        let config = spawn_reload("upstream client", files, reload_interval, move || {
+            build_client_config(&tls_config)
+        })?;
+
+        Ok(Self {
+            config,
+            server_name,
+        })
+    }
+
+    /// A connector dialing upstream instances over TLS.
+    pub fn connector(&self) -> UpstreamConnector {
+        UpstreamConnector {
+            config: self.config.clone(),
+            server_name: self.server_name.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct UpstreamConnector {
+    config: Arc<ArcSwap<ClientConfig>>,
+    server_name: Option<String>,
+}
+
+impl Service<Uri> for UpstreamConnector {
+    type Response = TokioIo<client::TlsStream<TcpStream>>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let config = self.config.load_full();
+        let server_name = self.server_name.clone();
+
+        Box::pin(async move {
+            let host = uri
+                .host()
+                .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+                .ok_or_else(|| invalid_input(format!("Missing host in {}", uri)))?;
+            let port = uri.port_u16().unwrap_or(443);
+
+            // Instances usually register by IP, so the certificate is checked
+            // against the configured name when there is one.
+            let name = server_name.unwrap_or_else(|| host.to_string());
+            let name = ServerName::try_from(name)
+                .map_err(|err| invalid_input(format!("Invalid server name: {}", err)))?;
+
+            let stream = TcpStream::connect((host, port)).await?;
+            stream.set_nodelay(true)?;
+
+            let stream = TlsConnector::from(config).connect(name, stream).await?;
+
+            Ok(TokioIo::new(stream))
+        })
+    }
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn build_server_config(config: &ServerTlsConfig) -> anyhow::Result<ServerConfig> {
+    let certs = load_certs(&config.cert)?;
+    let key = load_key(&config.key)?;
+
+    let builder = match &config.client_ca {
+        Some(client_ca) => {
+            let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(client_ca)?));
+
+            let verifier = match config.require_client_cert {
+                true => verifier.build(),
+                false => verifier.allow_unauthenticated().build(),
+            }
+            .map_err(|err| anyhow!("Error building client verifier: {}", err))?;
+
+            ServerConfig::builder().with_client_cert_verifier(verifier)
+        }
+        None => ServerConfig::builder().with_no_client_auth(),
+    };
+
+    let mut server_config = builder
+        .with_single_cert(certs, key)
+        .map_err(|err| anyhow!("Invalid server certificate {}: {}", config.cert, err))?;
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+
+    Ok(server_config)
+}
+
+fn build_client_config(config: &ClientTlsConfig) -> anyhow::Result<ClientConfig> {
+    let builder = ClientConfig::builder().with_root_certificates(load_roots(&config.ca_cert)?);
+
+    let mut client_config = match (&config.cert, &config.key) {
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .map_err(|err| anyhow!("Invalid client certificate {}: {}", cert, err))?,
+        _ => builder.with_no_client_auth(),
+    };
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+
+    Ok(client_config)
+}
+
+fn load_certs(path: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| anyhow!("Error reading certificates from {}: {}", path, err))?;
+
+    if certs.is_empty() {
+        anyhow::bail!("No certificate found in {}", path);
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> anyhow::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|err| anyhow!("Error reading private key from {}: {}", path, err))
+}
+
+fn load_roots(path: &str) -> anyhow::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .map_err(|err| anyhow!("Invalid CA certificate in {}: {}", path, err))?;
+    }
+
+    Ok(roots)
+}
+
+/// Builds the config once, then polls its files and rebuilds it whenever one
+/// of them changes. A failed rebuild keeps the previous config and is retried
+/// on the next poll, as the files may still be being written.
+fn spawn_reload<T, F>(
+    name: &'static str,
+    files: Vec<PathBuf>,
+    interval: Duration,
+    build: F,
+) -> anyhow::Result<Arc<ArcSwap<T>>>
+where
+    T: Send + Sync + 'static,
+    F: Fn() -> anyhow::Result<T> + Send + 'static,
+{
+    let current = Arc::new(ArcSwap::from_pointee(build()?));
+    let reloaded = current.clone();
+
+    let mut modified = modified_times(&files);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval.max(Duration::from_secs(1)));
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            let latest = modified_times(&files);
+
+            if latest == modified {
+                continue;
+            }
+
+            match build() {
+                Ok(config) => {
+                    reloaded.store(Arc::new(config));
+                    modified = latest;
+
+                    info!("Reloaded {} TLS certificates", name);
+                }
+                Err(err) => warn!(
+                    "Error reloading {} TLS certificates, keeping the previous ones: {}",
+                    name, err
+                ),
+            }
+        }
+    });
+
+    Ok(current)
+}
+
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| {
+            std::fs::metadata(file)
+                .and_then(|meta| meta.modified())
+                .ok()
+        })
+        .collect()
+}
@@ -10,6 +10,13 @@ type AppConfig struct {
 	NATSURL    string `mapstructure:"mq_url"`
 	RedisAddr  string `mapstructure:"redis_address"`
 	ConsulAddr string `mapstructure:"consul_address"`
+	ServiceID  string `mapstructure:"service_id"`
+
+	// DispatchAuthKind is how dispatch calls authenticate to the connectors,
+	// "shared_secret" or "hmac" with the secret in DISPATCH_AUTH_SECRET, and
+	// none when empty. Roll it out to the dispatchers before turning on
+	// dispatch_auth on the connectors, which ignore credentials until then.
+	DispatchAuthKind string `mapstructure:"dispatch_auth_kind"`
 }
 
 func LoadConfig() (*AppConfig, error) {
@@ -30,6 +30,7 @@ func RunPullMsg(s *state.AppState) (*nats.Subscription, error) {
 				s.Rdb,
 				s.ChanSrv,
 				s.ConnSrv,
+				s.DispAuth,
 				s.DispPool,
 			)
 		},
@@ -47,6 +48,7 @@ func handleNATSMsg(
 	rdb *redis.Client,
 	chanSrv *state.ChanSrvBalancer,
 	connSrv *state.ConnSrvClient,
+	dispAuth *service.DispatchAuth,
 	exePool *ants.Pool,
 ) {
 	msgVO := &vo.ChanMsgVO{}
@@ -93,7 +95,7 @@ func handleNATSMsg(
 		exePool.Submit(func() {
 			cli := pb.NewDispatchServiceClient(conn)
 
-			if err := service.TransMsg(cli, target, msgVO); err != nil {
+			if err := service.TransMsg(cli, dispAuth, target, msgVO); err != nil {
 				slog.Error(
 					"failed to dispatch message to connector",
 					"connector_token", tkn,
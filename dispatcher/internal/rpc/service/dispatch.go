@@ -10,6 +10,7 @@ import (
 
 func TransMsg(
 	cli pb.DispatchServiceClient,
+	auth *DispatchAuth,
 	targetUserID string,
 	msg *vo.ChanMsgVO,
 ) error {
@@ -19,7 +20,13 @@ func TransMsg(
 
 	defer cancel()
 
-	_, err := cli.DispatchMessage(
+	ctx, err := auth.attach(ctx)
+
+	if err != nil {
+		return err
+	}
+
+	_, err = cli.DispatchMessage(
 		ctx,
 		&pb.DispatchMessageRequest{
 			TargetUserId: targetUserID,
@@ -2,6 +2,7 @@ package state
 
 import (
 	"dispatcher/internal/registry"
+	"dispatcher/internal/rpc/service"
 	"log/slog"
 
 	"github.com/ATOMLubover/balancer-go"
@@ -21,6 +22,7 @@ type ConnSrvClient = registry.ConsulClient[*grpc.ClientConn]
 type AppState struct {
 	ChanSrv  *ChanSrvBalancer
 	ConnSrv  *ConnSrvClient
+	DispAuth *service.DispatchAuth
 	NATSConn *nats.Conn
 	Rdb      *redis.Client
 	DispPool *ants.Pool
@@ -29,6 +31,7 @@ type AppState struct {
 func NewAppState(
 	chanSrv *ChanSrvBalancer,
 	connSrv *ConnSrvClient,
+	dispAuth *service.DispatchAuth,
 	natsConn *nats.Conn,
 	rdb *redis.Client,
 	dispPool *ants.Pool,
@@ -36,6 +39,7 @@ func NewAppState(
 	return &AppState{
 		ChanSrv:  chanSrv,
 		ConnSrv:  connSrv,
+		DispAuth: dispAuth,
 		NATSConn: natsConn,
 		Rdb:      rdb,
 		DispPool: dispPool,
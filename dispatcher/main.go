@@ -5,6 +5,7 @@ import (
 	"dispatcher/internal/config"
 	"dispatcher/internal/mq"
 	"dispatcher/internal/registry"
+	"dispatcher/internal/rpc/service"
 	"dispatcher/internal/state"
 	"errors"
 	"fmt"
@@ -85,6 +86,13 @@ func main() {
 
 	slog.Info("created service registry clients successfully")
 
+	dispAuth, err := initDispAuth(cfg)
+
+	if err != nil {
+		slog.Error("failed to initialize dispatch auth", "error", err.Error())
+		return
+	}
+
 	dispPool, err := initPool()
 
 	if err != nil {
@@ -92,7 +100,7 @@ func main() {
 		return
 	}
 
-	state, err := initState(chanSrv, connSrv, natsConn, rdb, dispPool)
+	state, err := initState(chanSrv, connSrv, dispAuth, natsConn, rdb, dispPool)
 
 	if err != nil {
 		slog.Error("failed to initialize state", "error", err.Error())
@@ -179,6 +187,14 @@ func initConnSrv(cfg *config.AppConfig, stopCh <-chan struct{}) (*registry.Consu
 	return newConnSrv(cfg.ConsulAddr, stopCh)
 }
 
+func initDispAuth(cfg *config.AppConfig) (*service.DispatchAuth, error) {
+	return service.NewDispatchAuth(
+		cfg.DispatchAuthKind,
+		cfg.ServiceID,
+		os.Getenv("DISPATCH_AUTH_SECRET"),
+	)
+}
+
 func initPool() (*ants.Pool, error) {
 	return ants.NewPool(64)
 }
@@ -186,6 +202,7 @@ func initPool() (*ants.Pool, error) {
 func initState(
 	chanSrv *state.ChanSrvBalancer,
 	connSrv *registry.ConsulClient[*grpc.ClientConn],
+	dispAuth *service.DispatchAuth,
 	natsConn *nats.Conn,
 	rdb *redis.Client,
 	dispPool *ants.Pool,
@@ -193,6 +210,7 @@ func initState(
 	return state.NewAppState(
 		chanSrv,
 		connSrv,
+		dispAuth,
 		natsConn,
 		rdb,
 		dispPool,